@@ -0,0 +1,309 @@
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode, Version},
+    response::Response,
+};
+use futures::future::BoxFuture;
+use tower::{Layer, Service};
+
+use crate::{
+    asgi::Backend,
+    forwarded::{ForwardedInfo, ProxyHeaders},
+};
+
+/// uvicorn's default access log format
+const DEFAULT_FORMAT: &str = "%(client_addr)s - \"%(request_line)s\" %(status_code)s";
+
+/// A layer that writes an access log line for every request, whether it was served by a rust
+/// handler or forwarded to the `AsgiHandler`.
+///
+/// With the `tracing` feature the lines are emitted as `tracing` events with the
+/// `parviocula::access` target, formatted using the format set by [`AccessLogLayer::with_format`].
+/// Otherwise they are passed to python's `logging` using the `uvicorn.access` logger and the
+/// same record arguments uvicorn uses, so uvicorn's `AccessFormatter` and existing log configs
+/// keep working. The `backend`, `latency_ms` and the formatted `access_line` are added to the
+/// log record as extras. Python logging happens on a dedicated thread so requests never wait
+/// for the GIL, and lines are dropped when that thread falls behind.
+#[derive(Clone)]
+pub struct AccessLogLayer {
+    config: Arc<AccessLogConfig>,
+}
+
+#[derive(Clone)]
+struct AccessLogConfig {
+    format: String,
+    #[cfg(not(feature = "tracing"))]
+    logger_name: String,
+    proxy_headers: Option<ProxyHeaders>,
+}
+
+impl AccessLogLayer {
+    pub fn new() -> AccessLogLayer {
+        AccessLogLayer {
+            config: Arc::new(AccessLogConfig {
+                format: DEFAULT_FORMAT.to_string(),
+                #[cfg(not(feature = "tracing"))]
+                logger_name: "uvicorn.access".to_string(),
+                proxy_headers: None,
+            }),
+        }
+    }
+
+    /// Set the format of the access log lines, using python's `%(name)s` style placeholders.
+    ///
+    /// Supports uvicorn's `client_addr`, `request_line` and `status_code` fields, as well as
+    /// `method`, `path`, `http_version`, `latency_ms` and `backend` (`rust` or `asgi`).
+    pub fn with_format(mut self, format: impl Into<String>) -> AccessLogLayer {
+        Arc::make_mut(&mut self.config).format = format.into();
+        self
+    }
+
+    /// Set the name of the python logger the lines are passed to.
+    #[cfg(not(feature = "tracing"))]
+    pub fn with_logger_name(mut self, logger_name: impl Into<String>) -> AccessLogLayer {
+        Arc::make_mut(&mut self.config).logger_name = logger_name.into();
+        self
+    }
+
+    /// Resolve the logged client address from the proxy headers of trusted proxies. This
+    /// should match the `ProxyHeaders` given to
+    /// [`AsgiHandler::with_proxy_headers`](crate::AsgiHandler::with_proxy_headers).
+    ///
+    /// If this isn't set, the `ProxyHeaders` from the request extensions are used if present.
+    pub fn with_proxy_headers(mut self, proxy_headers: ProxyHeaders) -> AccessLogLayer {
+        Arc::make_mut(&mut self.config).proxy_headers = Some(proxy_headers);
+        self
+    }
+}
+
+impl Default for AccessLogLayer {
+    fn default() -> Self {
+        AccessLogLayer::new()
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLog<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLog {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AccessLog<S> {
+    inner: S,
+    config: Arc<AccessLogConfig>,
+}
+
+impl<S> Service<Request<Body>> for AccessLog<S>
+where
+    S: Service<Request<Body>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let start = Instant::now();
+        let (parts, body) = req.into_parts();
+        let proxy_headers = self
+            .config
+            .proxy_headers
+            .as_ref()
+            .or_else(|| parts.extensions.get::<ProxyHeaders>());
+        let forwarded = ForwardedInfo::from_parts(&parts, proxy_headers);
+        let mut entry = AccessLogEntry {
+            client: forwarded.client,
+            method: parts.method.clone(),
+            path: parts
+                .uri
+                .path_and_query()
+                .map(|path_and_query| path_and_query.as_str().to_string())
+                .unwrap_or_else(|| parts.uri.path().to_string()),
+            version: parts.version,
+            status: StatusCode::OK,
+            latency: Duration::ZERO,
+            backend: Backend::Rust,
+        };
+        let fut = self.inner.call(Request::from_parts(parts, body));
+        let config = self.config.clone();
+        Box::pin(async move {
+            let response = fut.await?;
+            entry.status = response.status();
+            entry.latency = start.elapsed();
+            entry.backend = Backend::from_response(&response);
+            config.log(entry);
+            Ok(response)
+        })
+    }
+}
+
+struct AccessLogEntry {
+    client: Option<(String, u16)>,
+    method: Method,
+    path: String,
+    version: Version,
+    status: StatusCode,
+    latency: Duration,
+    backend: Backend,
+}
+
+impl AccessLogEntry {
+    fn client_addr(&self) -> String {
+        match &self.client {
+            Some((host, port)) => format!("{host}:{port}"),
+            None => "-".to_string(),
+        }
+    }
+
+    fn http_version(&self) -> &'static str {
+        match self.version {
+            Version::HTTP_09 => "0.9",
+            Version::HTTP_10 => "1.0",
+            Version::HTTP_11 => "1.1",
+            Version::HTTP_2 => "2",
+            Version::HTTP_3 => "3",
+            _ => "-",
+        }
+    }
+
+    fn latency_ms(&self) -> f64 {
+        self.latency.as_secs_f64() * 1000.0
+    }
+
+    fn field(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "client_addr" => self.client_addr(),
+            "request_line" => format!("{} {} HTTP/{}", self.method, self.path, self.http_version()),
+            "status_code" => match self.status.canonical_reason() {
+                Some(reason) => format!("{} {reason}", self.status.as_u16()),
+                None => self.status.as_u16().to_string(),
+            },
+            "method" => self.method.to_string(),
+            "path" => self.path.clone(),
+            "http_version" => self.http_version().to_string(),
+            "latency_ms" => format!("{:.2}", self.latency_ms()),
+            "backend" => self.backend.as_str().to_string(),
+            _ => return None,
+        })
+    }
+
+    /// Render the `%(name)s` placeholders in the format. Unknown placeholders are left as is.
+    fn format(&self, format: &str) -> String {
+        let mut line = String::with_capacity(format.len());
+        let mut rest = format;
+        while let Some(start) = rest.find("%(") {
+            line.push_str(&rest[..start]);
+            let placeholder = &rest[start..];
+            match placeholder
+                .find(")s")
+                .and_then(|end| Some((end, self.field(&placeholder[2..end])?)))
+            {
+                Some((end, value)) => {
+                    line.push_str(&value);
+                    rest = &placeholder[end + 2..];
+                }
+                None => {
+                    line.push_str("%(");
+                    rest = &placeholder[2..];
+                }
+            }
+        }
+        line.push_str(rest);
+        line
+    }
+}
+
+impl AccessLogConfig {
+    #[cfg(feature = "tracing")]
+    fn log(self: &Arc<Self>, entry: AccessLogEntry) {
+        tracing::info!(
+            target: "parviocula::access",
+            backend = entry.backend.as_str(),
+            client = %entry.client_addr(),
+            method = %entry.method,
+            path = %entry.path,
+            status = entry.status.as_u16(),
+            latency_ms = entry.latency_ms(),
+            "{}",
+            entry.format(&self.format)
+        );
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    fn log(self: &Arc<Self>, entry: AccessLogEntry) {
+        // taking the gil on the tokio workers would stall every request behind python, so the
+        // entries are logged from a dedicated thread, and dropped if it can't keep up
+        let _ = python_logger().try_send((self.clone(), entry));
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    fn log_python(&self, entry: &AccessLogEntry) {
+        use pyo3::{prelude::*, types::PyDict};
+
+        // ignore any errors, there's nowhere to log them to
+        let _ = Python::with_gil(|py| {
+            let logger = py
+                .import("logging")?
+                .call_method1("getLogger", (&self.logger_name,))?;
+            let extra = PyDict::new(py);
+            extra.set_item("backend", entry.backend.as_str())?;
+            extra.set_item("latency_ms", entry.latency_ms())?;
+            extra.set_item("access_line", entry.format(&self.format))?;
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("extra", extra)?;
+            // the same message and arguments as uvicorn's httptools and h11 protocols
+            let args = (
+                "%s - \"%s %s HTTP/%s\" %d",
+                entry.client_addr(),
+                entry.method.as_str(),
+                &entry.path,
+                entry.http_version(),
+                entry.status.as_u16(),
+            );
+            logger.call_method("info", args, Some(&kwargs))?;
+            Ok::<_, PyErr>(())
+        });
+    }
+}
+
+/// The number of entries waiting for the logging thread before new ones are dropped
+#[cfg(not(feature = "tracing"))]
+const PYTHON_LOG_CAPACITY: usize = 1024;
+
+#[cfg(not(feature = "tracing"))]
+type PythonLogSender = std::sync::mpsc::SyncSender<(Arc<AccessLogConfig>, AccessLogEntry)>;
+
+/// Start the thread passing the entries to python's `logging` on first use
+#[cfg(not(feature = "tracing"))]
+fn python_logger() -> &'static PythonLogSender {
+    static SENDER: std::sync::OnceLock<PythonLogSender> = std::sync::OnceLock::new();
+    SENDER.get_or_init(|| {
+        let (tx, rx) =
+            std::sync::mpsc::sync_channel::<(Arc<AccessLogConfig>, _)>(PYTHON_LOG_CAPACITY);
+        std::thread::Builder::new()
+            .name("parviocula-access-log".to_string())
+            .spawn(move || {
+                for (config, entry) in rx {
+                    config.log_python(&entry);
+                }
+            })
+            .expect("failed to spawn the access log thread");
+        tx
+    })
+}
@@ -1,10 +1,17 @@
+use crate::event_loops::{EventLoopApps, LoopApp};
+use crate::extensions::ExtensionsConverter;
+use crate::forwarded::{ForwardedInfo, ProxyHeaders};
+use crate::inventory::{python_routes, PythonRoute};
+use crate::request::{mount_path, DEFAULT_MAX_BODY_SIZE};
+use crate::request_trace::RequestTrace;
 use crate::Sender;
 use axum::{
     body::{to_bytes, Body, Bytes},
     handler::Handler,
-    http::{HeaderName, HeaderValue, Request, StatusCode, Version},
+    http::{response, HeaderName, HeaderValue, Request, StatusCode, Version},
     response::{IntoResponse, Response},
 };
+use serde::de::DeserializeOwned;
 
 use pyo3::types::{PyBytes, PyDict, PyInt, PyString};
 use pyo3::{
@@ -14,12 +21,14 @@ use pyo3::{
     DowncastError, DowncastIntoError,
 };
 use std::{
+    fmt,
     future::Future,
     pin::Pin,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    time::Instant,
 };
 use tokio::sync::{
     mpsc::{self, UnboundedReceiver},
@@ -30,6 +39,14 @@ use tokio::sync::{
 pub struct AsgiHandler {
     app: Arc<PyObject>,
     locals: Arc<pyo3_async_runtimes::TaskLocals>,
+    proxy_headers: Option<ProxyHeaders>,
+    converters: Vec<Arc<dyn ExtensionsConverter>>,
+    lifespan_state: Option<Arc<Py<PyDict>>>,
+    event_loops: Option<Arc<EventLoopApps>>,
+    #[cfg(feature = "opentelemetry")]
+    trace_context: Option<crate::trace_context::TraceContext>,
+    #[cfg(feature = "record")]
+    recorder: Option<crate::record::Recorder>,
 }
 
 impl AsgiHandler {
@@ -37,7 +54,197 @@ impl AsgiHandler {
         app: Arc<PyObject>,
         locals: Arc<pyo3_async_runtimes::TaskLocals>,
     ) -> AsgiHandler {
-        AsgiHandler { app, locals }
+        AsgiHandler {
+            app,
+            locals,
+            proxy_headers: None,
+            converters: Vec::new(),
+            lifespan_state: None,
+            event_loops: None,
+            #[cfg(feature = "opentelemetry")]
+            trace_context: None,
+            #[cfg(feature = "record")]
+            recorder: None,
+        }
+    }
+
+    /// Resolve the `client`, `scheme` and `server` scope entries from the proxy headers of
+    /// requests coming from trusted proxies.
+    ///
+    /// If this isn't set, the `ProxyHeaders` from the request extensions are used if present.
+    pub fn with_proxy_headers(mut self, proxy_headers: ProxyHeaders) -> AsgiHandler {
+        self.proxy_headers = Some(proxy_headers);
+        self
+    }
+
+    /// Shallow copy the lifespan `state` into the scope of every request
+    pub(crate) fn with_lifespan_state(mut self, state: Arc<Py<PyDict>>) -> AsgiHandler {
+        self.lifespan_state = Some(state);
+        self
+    }
+
+    /// Spread requests across copies of the application on several event loops
+    pub(crate) fn with_event_loops(mut self, event_loops: EventLoopApps) -> AsgiHandler {
+        self.event_loops = Some(Arc::new(event_loops));
+        self
+    }
+
+    /// The event loop of the next request, taking turns when there are several
+    pub(crate) fn next_locals(&self) -> Arc<pyo3_async_runtimes::TaskLocals> {
+        match &self.event_loops {
+            Some(event_loops) => event_loops.next().locals.clone(),
+            None => self.locals.clone(),
+        }
+    }
+
+    /// Add a converter passing request extensions, e.g. set by rust middleware, to the ASGI
+    /// application in `scope["state"]` or `scope["extensions"]`. Converters run in the order
+    /// they're added.
+    pub fn with_extensions_converter<C>(mut self, converter: C) -> AsgiHandler
+    where
+        C: ExtensionsConverter,
+    {
+        self.converters.push(Arc::new(converter));
+        self
+    }
+
+    /// Send a request to the ASGI application, e.g. from a rust handler that post-processes the
+    /// python response.
+    ///
+    /// Named so it doesn't shadow [`Handler::call`], which takes the router state as well.
+    pub async fn send(self, req: Request<Body>) -> Response {
+        Handler::<AsgiHandler, ()>::call(self, req, ()).await
+    }
+
+    /// Send a request to the ASGI application and deserialize the JSON response body.
+    ///
+    /// Responses without a `2xx` status are returned as [`AsgiCallError::Status`], so they can
+    /// be passed on to the client unchanged.
+    pub async fn send_json<T: DeserializeOwned>(
+        self,
+        req: Request<Body>,
+    ) -> Result<(response::Parts, T), AsgiCallError> {
+        json_response(self.send(req).await).await
+    }
+
+    /// The routes of the python application, see [`python_routes`](crate::python_routes)
+    pub fn python_routes(&self) -> PyResult<Vec<PythonRoute>> {
+        Python::with_gil(|py| python_routes(self.app.bind(py)))
+    }
+
+    /// Propagate the opentelemetry trace context of each request into the ASGI application.
+    #[cfg(feature = "opentelemetry")]
+    pub fn with_trace_context(
+        mut self,
+        trace_context: crate::trace_context::TraceContext,
+    ) -> AsgiHandler {
+        self.trace_context = Some(trace_context);
+        self
+    }
+
+    /// Record a sample of the requests and the responses of the ASGI application.
+    #[cfg(feature = "record")]
+    pub fn with_recorder(mut self, recorder: crate::record::Recorder) -> AsgiHandler {
+        self.recorder = Some(recorder);
+        self
+    }
+}
+
+/// Deserialize the JSON body of a successful response, see [`AsgiHandler::send_json`]
+pub(crate) async fn json_response<T: DeserializeOwned>(
+    response: Response,
+) -> Result<(response::Parts, T), AsgiCallError> {
+    if !response.status().is_success() {
+        return Err(AsgiCallError::Status(response));
+    }
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX)
+        .await
+        .map_err(AsgiCallError::Body)?;
+    let value = serde_json::from_slice(&body).map_err(AsgiCallError::Json)?;
+    Ok((parts, value))
+}
+
+/// Which backend served a request.
+///
+/// The `AsgiHandler` inserts `Backend::Asgi` into the extensions of every response it creates,
+/// so any response without it was served by a rust handler.
+///
+/// `Asgi` stands for python as a whole: the other handlers running python code, i.e. the
+/// `WsgiHandler`, `PyFunctionHandler`, `AsgiWorkerPool` and `ProxyHandler`, mark their
+/// responses with it too, so the migration statistics and logs count everything still served
+/// by python under one backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Backend {
+    Rust,
+    /// Python, whether an in process ASGI application or any other python handler
+    Asgi,
+}
+
+impl Backend {
+    pub fn from_response<B>(response: &Response<B>) -> Backend {
+        response
+            .extensions()
+            .get::<Backend>()
+            .copied()
+            .unwrap_or(Backend::Rust)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Backend::Rust => "rust",
+            Backend::Asgi => "asgi",
+        }
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rust" => Ok(Backend::Rust),
+            "asgi" | "python" => Ok(Backend::Asgi),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Returned by [`AsgiHandler::send_json`]
+#[derive(Debug)]
+pub enum AsgiCallError {
+    /// The ASGI application responded with a non `2xx` status
+    Status(Response),
+    /// Failed to read the response body
+    Body(axum::Error),
+    /// The response body isn't valid JSON for the requested type
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for AsgiCallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsgiCallError::Status(response) => {
+                write!(f, "ASGI application responded with {}", response.status())
+            }
+            AsgiCallError::Body(e) => write!(f, "failed to read ASGI response body: {e}"),
+            AsgiCallError::Json(e) => write!(f, "invalid JSON in ASGI response: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for AsgiCallError {}
+
+impl IntoResponse for AsgiCallError {
+    fn into_response(self) -> Response {
+        match self {
+            AsgiCallError::Status(response) => response,
+            AsgiCallError::Body(_) | AsgiCallError::Json(_) => {
+                #[cfg(feature = "tracing")]
+                tracing::error!("{self}");
+                StatusCode::BAD_GATEWAY.into_response()
+            }
+        }
     }
 }
 
@@ -123,8 +330,7 @@ impl HttpReceiver {
                         Ok::<_, PyErr>(scope.into())
                     })
                 } else if let Some(Some(body)) = next {
-                    const MAX_BODY_SIZE: usize = 4 * 1024 * 1024; // 4MB
-                    let bytes = to_bytes(body, MAX_BODY_SIZE)
+                    let bytes = to_bytes(body, DEFAULT_MAX_BODY_SIZE)
                         .await
                         .map_err(|_e| PyErr::new::<PyRuntimeError, _>("failed to fetch data"))?;
                     Python::with_gil(|py| {
@@ -151,6 +357,25 @@ impl<S> Handler<AsgiHandler, S> for AsgiHandler {
     type Future = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
 
     fn call(self, req: Request<Body>, _state: S) -> Self::Future {
+        #[cfg(feature = "record")]
+        if let Some(recorder) = self.recorder.clone().filter(|recorder| recorder.sample()) {
+            let handler = AsgiHandler {
+                recorder: None,
+                ..self
+            };
+            return recorder.record(handler, req);
+        }
+        if let Some(event_loops) = self.event_loops.clone() {
+            let LoopApp { app, locals, state } = event_loops.next();
+            let handler = AsgiHandler {
+                app: app.clone(),
+                locals: locals.clone(),
+                lifespan_state: Some(state.clone()),
+                event_loops: None,
+                ..self
+            };
+            return Handler::<AsgiHandler, S>::call(handler, req, _state);
+        }
         let app = self.app.clone();
         let (http_sender, mut http_sender_rx) = Sender::new(self.locals.clone());
         let disconnected = Arc::new(AtomicBool::new(false));
@@ -161,226 +386,297 @@ impl<S> Handler<AsgiHandler, S> for AsgiHandler {
             locals: self.locals.clone(),
         };
         let (req, body): (_, Body) = req.into_parts();
-        Box::pin(async move {
-            receiver_tx.send(Some(body)).unwrap();
-            let _disconnected = SetTrueOnDrop(disconnected);
-            match Python::with_gil(|py| {
-                let asgi = PyDict::new(py);
-                asgi.set_item("spec_version", "2.0")?;
-                asgi.set_item("version", "2.0")?;
-                let scope = PyDict::new(py);
-                scope.set_item("type", "http")?;
-                scope.set_item("asgi", asgi)?;
-                scope.set_item(
-                    "http_version",
-                    match req.version {
-                        Version::HTTP_10 => "1.0",
-                        Version::HTTP_11 => "1.1",
-                        Version::HTTP_2 => "2",
-                        _ => return Err(AsgiError::InvalidHttpVersion),
-                    },
-                )?;
-                scope.set_item("method", req.method.as_str())?;
-                scope.set_item("scheme", req.uri.scheme_str().unwrap_or("http"))?;
-                if let Some(path_and_query) = req.uri.path_and_query() {
-                    let path = path_and_query.path();
-                    let raw_path = path.as_bytes();
-                    // the spec requires this to be percent decoded at this point
-                    // https://asgi.readthedocs.io/en/latest/specs/www.html#http-connection-scope
-                    let path = percent_encoding::percent_decode(raw_path)
-                        .decode_utf8()
-                        .map_err(|_| AsgiError::InvalidUtf8InPath)?;
-                    scope.set_item("path", path)?;
-                    let raw_path_bytes = PyBytes::new(py, path_and_query.path().as_bytes());
-                    scope.set_item("raw_path", raw_path_bytes)?;
-                    if let Some(query) = path_and_query.query() {
-                        let qs_bytes = PyBytes::new(py, query.as_bytes());
-                        scope.set_item("query_string", qs_bytes)?;
+        let mut trace = RequestTrace::new(&req);
+        #[cfg(feature = "tracing")]
+        let span = trace.span().clone();
+        let response = async move {
+            let mut response = async {
+                receiver_tx.send(Some(body)).unwrap();
+                let _disconnected = SetTrueOnDrop(disconnected);
+                let gil_wait = Instant::now();
+                match Python::with_gil(|py| {
+                    trace.gil_acquired(gil_wait.elapsed());
+                    let scope_start = Instant::now();
+                    let asgi = PyDict::new(py);
+                    asgi.set_item("spec_version", "2.0")?;
+                    asgi.set_item("version", "2.0")?;
+                    let scope = PyDict::new(py);
+                    scope.set_item("type", "http")?;
+                    scope.set_item("asgi", asgi)?;
+                    scope.set_item(
+                        "http_version",
+                        match req.version {
+                            Version::HTTP_10 => "1.0",
+                            Version::HTTP_11 => "1.1",
+                            Version::HTTP_2 => "2",
+                            _ => return Err(AsgiError::InvalidHttpVersion),
+                        },
+                    )?;
+                    scope.set_item("method", req.method.as_str())?;
+                    let proxy_headers = self
+                        .proxy_headers
+                        .as_ref()
+                        .or_else(|| req.extensions.get::<ProxyHeaders>());
+                    let forwarded = ForwardedInfo::from_parts(&req, proxy_headers);
+                    scope.set_item("scheme", forwarded.scheme)?;
+                    if let Some(client) = forwarded.client {
+                        scope.set_item("client", client)?;
+                    }
+                    if let Some(server) = forwarded.server {
+                        scope.set_item("server", server)?;
+                    }
+                    let (root_path, full_path) = mount_path(&req);
+                    if let Some(path_and_query) = req.uri.path_and_query() {
+                        let raw_path = full_path.as_bytes();
+                        // the spec requires this to be percent decoded at this point
+                        // https://asgi.readthedocs.io/en/latest/specs/www.html#http-connection-scope
+                        let path = percent_encoding::percent_decode(raw_path)
+                            .decode_utf8()
+                            .map_err(|_| AsgiError::InvalidUtf8InPath)?;
+                        scope.set_item("path", path)?;
+                        let raw_path_bytes = PyBytes::new(py, raw_path);
+                        scope.set_item("raw_path", raw_path_bytes)?;
+                        if let Some(query) = path_and_query.query() {
+                            let qs_bytes = PyBytes::new(py, query.as_bytes());
+                            scope.set_item("query_string", qs_bytes)?;
+                        } else {
+                            let qs_bytes = PyBytes::new(py, "".as_bytes());
+                            scope.set_item("query_string", qs_bytes)?;
+                        }
                     } else {
+                        // TODO: is it even possible to get here?
+                        // we have to set these to something as they're not optional in the spec
+                        scope.set_item("path", "")?;
+                        let raw_path_bytes = PyBytes::new(py, "".as_bytes());
+                        scope.set_item("raw_path", raw_path_bytes)?;
                         let qs_bytes = PyBytes::new(py, "".as_bytes());
                         scope.set_item("query_string", qs_bytes)?;
                     }
-                } else {
-                    // TODO: is it even possible to get here?
-                    // we have to set these to something as they're not optional in the spec
-                    scope.set_item("path", "")?;
-                    let raw_path_bytes = PyBytes::new(py, "".as_bytes());
-                    scope.set_item("raw_path", raw_path_bytes)?;
-                    let qs_bytes = PyBytes::new(py, "".as_bytes());
-                    scope.set_item("query_string", qs_bytes)?;
-                }
-                scope.set_item("root_path", "")?;
-
-                let headers = req
-                    .headers
-                    .iter()
-                    .map(|(name, value)| {
-                        let name_bytes = PyBytes::new(py, name.as_str().as_bytes());
-                        let value_bytes = PyBytes::new(py, value.as_bytes());
-                        // This unwrap() is safe because PyList::new only fails if there's a Python
-                        // exception during list creation, which won't happen for a simple list of
-                        // two PyBytes objects that were just successfully created
-                        PyList::new(py, [name_bytes, value_bytes]).unwrap()
-                    })
-                    .collect::<Vec<_>>();
-                // This unwrap() is safe because PyList::new only fails if there's a Python
-                // exception during list creation, which won't happen for a simple list of
-                // PyList objects that were already successfully created above
-                let headers = PyList::new(py, headers).unwrap();
-                scope.set_item("headers", headers)?;
-                // TODO: client/server args
-                let sender = Py::new(py, http_sender)?;
-                let receiver = Py::new(py, receiver)?;
-                let args = (scope, receiver, sender);
-                let res = app.call_method1(py, "__call__", args)?;
-                let fut = res.extract(py)?;
-                let coro = pyo3_async_runtimes::into_future_with_locals(&self.locals, fut)?;
-                Ok::<_, AsgiError>(coro)
-            }) {
-                Ok(http_coro) => {
-                    tokio::spawn(async move {
-                        if let Err(_e) = http_coro.await {
-                            #[cfg(feature = "tracing")]
-                            tracing::error!("error handling request: {_e}");
-                        }
-                    });
-
-                    let mut response = Response::builder();
-
-                    if let Some(resp) = http_sender_rx.recv().await {
-                        let (status, headers) = match Python::with_gil(|py| {
-                            let dict: Bound<'_, PyDict> = resp.into_bound(py);
-                            if let Ok(Some(value)) = dict.get_item("type") {
-                                let value: Bound<'_, PyString> = value.downcast_into()?;
-                                let value = value.to_str()?;
-                                if value == "http.response.start" {
-                                    let value: Bound<'_, PyInt> = dict
-                                        .get_item("status")
-                                        .and_then(|opt| {
-                                            opt.ok_or_else(|| {
-                                                PyErr::new::<PyRuntimeError, _>(
-                                                    "Missing status in http.response.start",
-                                                )
-                                            })
-                                        })?
-                                        .downcast_into()?;
-                                    let status: u16 = value.extract()?;
-
-                                    let headers = if let Ok(Some(raw)) = dict.get_item("headers") {
-                                        let outer: Bound<'_, PySequence> = raw.downcast_into()?;
-                                        Some(
-                                            outer
-                                                .try_iter()?
-                                                .map(|item| {
-                                                    item.and_then(|item| {
-                                                        let seq: Bound<'_, PySequence> =
-                                                            item.downcast_into()?;
-                                                        let header: Vec<u8> =
-                                                            seq.get_item(0)?.extract()?;
-                                                        let value: Vec<u8> =
-                                                            seq.get_item(1)?.extract()?;
-                                                        Ok((header, value))
-                                                    })
+                    let root_path = percent_encoding::percent_decode(root_path.as_bytes())
+                        .decode_utf8()
+                        .map_err(|_| AsgiError::InvalidUtf8InPath)?;
+                    scope.set_item("root_path", root_path)?;
+
+                    let headers = req
+                        .headers
+                        .iter()
+                        .map(|(name, value)| {
+                            let name_bytes = PyBytes::new(py, name.as_str().as_bytes());
+                            let value_bytes = PyBytes::new(py, value.as_bytes());
+                            // This unwrap() is safe because PyList::new only fails if there's a Python
+                            // exception during list creation, which won't happen for a simple list of
+                            // two PyBytes objects that were just successfully created
+                            PyList::new(py, [name_bytes, value_bytes]).unwrap()
+                        })
+                        .collect::<Vec<_>>();
+                    // This unwrap() is safe because PyList::new only fails if there's a Python
+                    // exception during list creation, which won't happen for a simple list of
+                    // PyList objects that were already successfully created above
+                    let headers = PyList::new(py, headers).unwrap();
+                    let extensions = PyDict::new(py);
+                    let state = match &self.lifespan_state {
+                        Some(lifespan_state) => lifespan_state.bind(py).copy()?,
+                        None => PyDict::new(py),
+                    };
+                    for converter in &self.converters {
+                        converter.convert(py, &req.extensions, &state, &extensions)?;
+                    }
+                    #[cfg(feature = "tls")]
+                    if let Some(tls_info) = req.extensions.get::<crate::tls::TlsInfo>() {
+                        extensions.set_item("tls", tls_info.to_extension(py)?)?;
+                    }
+                    #[cfg(feature = "opentelemetry")]
+                    let request_locals = match &self.trace_context {
+                        Some(trace_context) => trace_context.inject(
+                            py,
+                            trace.span(),
+                            &headers,
+                            &extensions,
+                            &self.locals,
+                        )?,
+                        None => None,
+                    };
+                    #[cfg(not(feature = "opentelemetry"))]
+                    let request_locals = None;
+                    scope.set_item("headers", headers)?;
+                    if !extensions.is_empty() {
+                        scope.set_item("extensions", extensions)?;
+                    }
+                    if !state.is_empty() {
+                        scope.set_item("state", state)?;
+                    }
+                    trace.scope_built(scope_start.elapsed());
+                    let sender = Py::new(py, http_sender)?;
+                    let receiver = Py::new(py, receiver)?;
+                    let args = (scope, receiver, sender);
+                    let res = app.call_method1(py, "__call__", args)?;
+                    let fut = res.extract(py)?;
+                    let coro = pyo3_async_runtimes::into_future_with_locals(
+                        request_locals.as_ref().unwrap_or(&self.locals),
+                        fut,
+                    )?;
+                    Ok::<_, AsgiError>(coro)
+                }) {
+                    Ok(http_coro) => {
+                        let http_coro = async move {
+                            if let Err(_e) = http_coro.await {
+                                #[cfg(feature = "tracing")]
+                                tracing::error!("error handling request: {_e}");
+                            }
+                        };
+                        #[cfg(feature = "tracing")]
+                        let http_coro =
+                            tracing::Instrument::instrument(http_coro, trace.span().clone());
+                        tokio::spawn(http_coro);
+
+                        let mut response = Response::builder();
+
+                        if let Some(resp) = http_sender_rx.recv().await {
+                            let (status, headers) = match Python::with_gil(|py| {
+                                let dict: Bound<'_, PyDict> = resp.into_bound(py);
+                                if let Ok(Some(value)) = dict.get_item("type") {
+                                    let value: Bound<'_, PyString> = value.downcast_into()?;
+                                    let value = value.to_str()?;
+                                    if value == "http.response.start" {
+                                        let value: Bound<'_, PyInt> = dict
+                                            .get_item("status")
+                                            .and_then(|opt| {
+                                                opt.ok_or_else(|| {
+                                                    PyErr::new::<PyRuntimeError, _>(
+                                                        "Missing status in http.response.start",
+                                                    )
                                                 })
-                                                .collect::<PyResult<Vec<_>>>()?,
-                                        )
+                                            })?
+                                            .downcast_into()?;
+                                        let status: u16 = value.extract()?;
+
+                                        let headers =
+                                            if let Ok(Some(raw)) = dict.get_item("headers") {
+                                                let outer: Bound<'_, PySequence> =
+                                                    raw.downcast_into()?;
+                                                Some(
+                                                    outer
+                                                        .try_iter()?
+                                                        .map(|item| {
+                                                            item.and_then(|item| {
+                                                                let seq: Bound<'_, PySequence> =
+                                                                    item.downcast_into()?;
+                                                                let header: Vec<u8> =
+                                                                    seq.get_item(0)?.extract()?;
+                                                                let value: Vec<u8> =
+                                                                    seq.get_item(1)?.extract()?;
+                                                                Ok((header, value))
+                                                            })
+                                                        })
+                                                        .collect::<PyResult<Vec<_>>>()?,
+                                                )
+                                            } else {
+                                                None
+                                            };
+                                        Ok((status, headers))
                                     } else {
-                                        None
-                                    };
-                                    Ok((status, headers))
+                                        Err(AsgiError::ExpectedResponseStart)
+                                    }
                                 } else {
                                     Err(AsgiError::ExpectedResponseStart)
                                 }
-                            } else {
-                                Err(AsgiError::ExpectedResponseStart)
-                            }
-                        }) {
-                            Ok((status, headers)) => (status, headers),
-                            Err(e) => {
-                                return e.into_response();
-                            }
-                        };
-                        response = response.status(status);
-                        if let Some(pyheaders) = headers {
-                            let headers = response.headers_mut().unwrap();
-                            for (name, value) in pyheaders {
-                                let name = match HeaderName::from_bytes(&name) {
-                                    Ok(name) => name,
-                                    Err(_e) => {
-                                        return AsgiError::InvalidHeader.into_response();
-                                    }
-                                };
-                                let value = match HeaderValue::from_bytes(&value) {
-                                    Ok(value) => value,
-                                    Err(_e) => {
-                                        return AsgiError::InvalidHeader.into_response();
-                                    }
-                                };
-                                headers.append(name, value);
+                            }) {
+                                Ok((status, headers)) => (status, headers),
+                                Err(e) => {
+                                    return e.into_response();
+                                }
+                            };
+                            trace.response_start(status);
+                            response = response.status(status);
+                            if let Some(pyheaders) = headers {
+                                let headers = response.headers_mut().unwrap();
+                                for (name, value) in pyheaders {
+                                    let name = match HeaderName::from_bytes(&name) {
+                                        Ok(name) => name,
+                                        Err(_e) => {
+                                            return AsgiError::InvalidHeader.into_response();
+                                        }
+                                    };
+                                    let value = match HeaderValue::from_bytes(&value) {
+                                        Ok(value) => value,
+                                        Err(_e) => {
+                                            return AsgiError::InvalidHeader.into_response();
+                                        }
+                                    };
+                                    headers.append(name, value);
+                                }
                             }
+                        } else {
+                            return AsgiError::MissingResponse.into_response();
                         }
-                    } else {
-                        return AsgiError::MissingResponse.into_response();
-                    }
 
-                    let mut body = Vec::new();
-                    while let Some(resp) = http_sender_rx.recv().await {
-                        let (bytes, more_body) = match Python::with_gil(|py| {
-                            let dict: Bound<'_, PyDict> = resp.into_bound(py);
-                            if let Ok(Some(value)) = dict.get_item("type") {
-                                let value: Bound<'_, PyString> =
-                                    value.downcast_into().map_err(|_| {
-                                        AsgiError::PyErr(PyErr::new::<PyRuntimeError, _>(
-                                            "failed to downcast type",
-                                        ))
-                                    })?;
-                                let value = value.to_str()?;
-                                if value == "http.response.body" {
-                                    let more_body =
-                                        if let Ok(Some(raw)) = dict.get_item("more_body") {
-                                            raw.extract::<bool>()?
+                        let mut body = Vec::new();
+                        while let Some(resp) = http_sender_rx.recv().await {
+                            let (bytes, more_body) = match Python::with_gil(|py| {
+                                let dict: Bound<'_, PyDict> = resp.into_bound(py);
+                                if let Ok(Some(value)) = dict.get_item("type") {
+                                    let value: Bound<'_, PyString> =
+                                        value.downcast_into().map_err(|_| {
+                                            AsgiError::PyErr(PyErr::new::<PyRuntimeError, _>(
+                                                "failed to downcast type",
+                                            ))
+                                        })?;
+                                    let value = value.to_str()?;
+                                    if value == "http.response.body" {
+                                        let more_body =
+                                            if let Ok(Some(raw)) = dict.get_item("more_body") {
+                                                raw.extract::<bool>()?
+                                            } else {
+                                                false
+                                            };
+                                        if let Ok(Some(raw)) = dict.get_item("body") {
+                                            Ok((raw.extract::<Vec<u8>>()?, more_body))
                                         } else {
-                                            false
-                                        };
-                                    if let Ok(Some(raw)) = dict.get_item("body") {
-                                        Ok((raw.extract::<Vec<u8>>()?, more_body))
+                                            Ok((Vec::new(), more_body))
+                                        }
                                     } else {
-                                        Ok((Vec::new(), more_body))
+                                        Err(AsgiError::ExpectedResponseBody)
                                     }
                                 } else {
                                     Err(AsgiError::ExpectedResponseBody)
                                 }
-                            } else {
-                                Err(AsgiError::ExpectedResponseBody)
-                            }
-                        }) {
-                            Ok((bytes, more_body)) => (bytes, more_body),
-                            Err(e) => {
-                                return e.into_response();
+                            }) {
+                                Ok((bytes, more_body)) => (bytes, more_body),
+                                Err(e) => {
+                                    return e.into_response();
+                                }
+                            };
+                            trace.body_chunk(bytes.len());
+                            body.extend(bytes);
+                            if !more_body {
+                                break;
                             }
-                        };
-                        body.extend(bytes);
-                        if !more_body {
-                            break;
                         }
-                    }
 
-                    let body = Body::from(Bytes::from(body));
-                    match response.body(body) {
-                        Ok(response) => response.into_response(),
-                        Err(_e) => {
-                            #[cfg(feature = "tracing")]
-                            tracing::error!("Failed to create response: {_e}");
-                            AsgiError::FailedToCreateResponse.into_response()
+                        let body = Body::from(Bytes::from(body));
+                        match response.body(body) {
+                            Ok(response) => response.into_response(),
+                            Err(_e) => {
+                                #[cfg(feature = "tracing")]
+                                tracing::error!("Failed to create response: {_e}");
+                                AsgiError::FailedToCreateResponse.into_response()
+                            }
                         }
                     }
-                }
-                Err(e) => {
-                    #[cfg(feature = "tracing")]
-                    tracing::error!("Error preparing request scope: {e:?}");
-                    e.into_response()
+                    Err(e) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::error!("Error preparing request scope: {e:?}");
+                        e.into_response()
+                    }
                 }
             }
-        })
+            .await;
+            trace.complete(response.status());
+            response.extensions_mut().insert(Backend::Asgi);
+            response
+        };
+        #[cfg(feature = "tracing")]
+        let response = tracing::Instrument::instrument(response, span);
+        Box::pin(response)
     }
 }
@@ -0,0 +1,379 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    http::{Method, Request},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::future::BoxFuture;
+use pyo3::{prelude::*, types::PyDict};
+use serde_json::json;
+use tower::{Layer, Service};
+
+use crate::{asgi::Backend, inventory::PythonRoute};
+
+/// Upper bounds of the latency histogram buckets, in microseconds (64µs up to ~67s)
+const BUCKETS: usize = 21;
+
+fn bucket_bound(index: usize) -> Duration {
+    Duration::from_micros(64 << index)
+}
+
+/// The route recorded for requests that didn't match any route once there are too many
+/// different paths
+const FALLBACK_ROUTE: &str = "fallback";
+
+/// The method recorded for requests using an extension method, as clients can send any method
+/// to a matched route
+const OTHER_METHOD: &str = "OTHER";
+
+/// The number of different routes after which unmatched paths are no longer recorded
+/// separately, so clients can't grow the statistics without bounds
+const MAX_ROUTES: usize = 1000;
+
+#[derive(Clone, Default)]
+struct RouteStats {
+    requests: u64,
+    errors: u64,
+    latency: [u64; BUCKETS],
+}
+
+impl RouteStats {
+    fn record(&mut self, latency: Duration, error: bool) {
+        self.requests += 1;
+        if error {
+            self.errors += 1;
+        }
+        let bucket = (0..BUCKETS)
+            .find(|index| latency <= bucket_bound(*index))
+            .unwrap_or(BUCKETS - 1);
+        self.latency[bucket] += 1;
+    }
+
+    /// An approximation of the percentile, as the upper bound of the bucket it falls in
+    fn percentile(&self, percentile: f64) -> Duration {
+        let target = ((self.requests as f64) * percentile / 100.0)
+            .ceil()
+            .max(1.0) as u64;
+        let mut seen = 0;
+        for (index, count) in self.latency.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return bucket_bound(index);
+            }
+        }
+        bucket_bound(BUCKETS - 1)
+    }
+}
+
+type Key = (Method, String, Backend);
+
+/// Collects per route statistics of which backend, rust or the ASGI application, served each
+/// request, to track how much traffic still depends on python.
+///
+/// Add [`Coverage::layer`] to the router with `Router::layer` so it sees the matched route of
+/// every request. Requests that don't match a rust route (i.e. the fallback) are recorded under
+/// the python route they match, see [`Coverage::with_python_routes`], or otherwise under their
+/// path. Past 1000 routes, unmatched paths are recorded under a single `fallback` route, and
+/// extension methods are always recorded as `OTHER`.
+/// Responses with a `5xx` status count as errors.
+#[derive(Clone, Default)]
+pub struct Coverage {
+    stats: Arc<Mutex<HashMap<Key, RouteStats>>>,
+    python_routes: Arc<Vec<PythonRoute>>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    pub fn layer(&self) -> CoverageLayer {
+        CoverageLayer {
+            coverage: self.clone(),
+        }
+    }
+
+    /// Record the requests that don't match a rust route under the python route they match,
+    /// e.g. `/users/{id}` rather than `/users/1`, using the routes from
+    /// [`python_routes`](crate::python_routes)
+    pub fn with_python_routes(mut self, routes: Vec<PythonRoute>) -> Coverage {
+        self.python_routes = Arc::new(routes);
+        self
+    }
+
+    /// The route of a request that didn't match a rust route, and whether it's the raw path
+    fn fallback_route(&self, path: &str) -> (String, bool) {
+        match self.python_routes.iter().find(|route| route.matches(path)) {
+            Some(route) => (route.path.clone(), false),
+            None => (path.to_string(), true),
+        }
+    }
+
+    fn record(&self, mut key: Key, raw_path: bool, latency: Duration, error: bool) {
+        if !is_standard_method(&key.0) {
+            key.0 = Method::from_bytes(OTHER_METHOD.as_bytes()).expect("invalid method");
+        }
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        if raw_path && stats.len() >= MAX_ROUTES && !stats.contains_key(&key) {
+            key.1 = FALLBACK_ROUTE.to_string();
+        }
+        stats.entry(key).or_default().record(latency, error);
+    }
+
+    /// A snapshot of the statistics collected so far
+    pub fn report(&self) -> CoverageReport {
+        let stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        let mut routes = stats
+            .iter()
+            .map(|((method, route, backend), stats)| RouteCoverage {
+                method: method.clone(),
+                route: route.clone(),
+                backend: *backend,
+                requests: stats.requests,
+                errors: stats.errors,
+                p50: stats.percentile(50.0),
+                p90: stats.percentile(90.0),
+                p99: stats.percentile(99.0),
+            })
+            .collect::<Vec<_>>();
+        routes.sort_by(|a, b| {
+            b.requests
+                .cmp(&a.requests)
+                .then_with(|| a.route.cmp(&b.route))
+                .then_with(|| a.method.as_str().cmp(b.method.as_str()))
+        });
+        CoverageReport { routes }
+    }
+
+    /// Clear all the statistics
+    pub fn reset(&self) {
+        self.stats.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+fn is_standard_method(method: &Method) -> bool {
+    [
+        Method::GET,
+        Method::HEAD,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::CONNECT,
+        Method::OPTIONS,
+        Method::TRACE,
+        Method::PATCH,
+    ]
+    .contains(method)
+}
+
+/// The statistics of a single method, route and backend.
+#[derive(Clone, Debug)]
+pub struct RouteCoverage {
+    pub method: Method,
+    pub route: String,
+    pub backend: Backend,
+    pub requests: u64,
+    pub errors: u64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+}
+
+impl RouteCoverage {
+    pub fn error_rate(&self) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            self.errors as f64 / self.requests as f64
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "method": self.method.as_str(),
+            "route": self.route,
+            "backend": self.backend.as_str(),
+            "requests": self.requests,
+            "errors": self.errors,
+            "error_rate": self.error_rate(),
+            "p50_ms": self.p50.as_secs_f64() * 1000.0,
+            "p90_ms": self.p90.as_secs_f64() * 1000.0,
+            "p99_ms": self.p99.as_secs_f64() * 1000.0,
+        })
+    }
+
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("method", self.method.as_str())?;
+        dict.set_item("route", &self.route)?;
+        dict.set_item("backend", self.backend.as_str())?;
+        dict.set_item("requests", self.requests)?;
+        dict.set_item("errors", self.errors)?;
+        dict.set_item("error_rate", self.error_rate())?;
+        dict.set_item("p50_ms", self.p50.as_secs_f64() * 1000.0)?;
+        dict.set_item("p90_ms", self.p90.as_secs_f64() * 1000.0)?;
+        dict.set_item("p99_ms", self.p99.as_secs_f64() * 1000.0)?;
+        Ok(dict)
+    }
+}
+
+/// A snapshot of the [`Coverage`] statistics, ordered by the number of requests.
+///
+/// Responds with the report as JSON, so it can be served directly from a route:
+///
+/// ```rust,ignore
+/// let coverage = Coverage::new();
+/// Router::new()
+///     .route("/_coverage", get({
+///         let coverage = coverage.clone();
+///         move || async move { coverage.report() }
+///     }))
+///     .fallback(asgi)
+///     .layer(coverage.layer())
+/// ```
+#[derive(Clone, Debug)]
+pub struct CoverageReport {
+    pub routes: Vec<RouteCoverage>,
+}
+
+impl CoverageReport {
+    pub fn requests(&self, backend: Backend) -> u64 {
+        self.routes
+            .iter()
+            .filter(|route| route.backend == backend)
+            .map(|route| route.requests)
+            .sum()
+    }
+
+    /// The share of all requests that were served by the ASGI application, from 0 to 1
+    pub fn asgi_share(&self) -> f64 {
+        let total = self.requests(Backend::Rust) + self.requests(Backend::Asgi);
+        if total == 0 {
+            0.0
+        } else {
+            self.requests(Backend::Asgi) as f64 / total as f64
+        }
+    }
+
+    /// The routes served by the ASGI application, ordered by the number of requests, i.e. the
+    /// candidates to port to rust next
+    pub fn asgi_routes(&self) -> Vec<&RouteCoverage> {
+        self.routes
+            .iter()
+            .filter(|route| route.backend == Backend::Asgi)
+            .collect()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "rust_requests": self.requests(Backend::Rust),
+            "asgi_requests": self.requests(Backend::Asgi),
+            "asgi_share": self.asgi_share(),
+            "routes": self.routes.iter().map(RouteCoverage::to_json).collect::<Vec<_>>(),
+        })
+    }
+
+    pub(crate) fn to_dicts<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.routes.iter().map(|route| route.to_dict(py)).collect()
+    }
+}
+
+impl IntoResponse for CoverageReport {
+    fn into_response(self) -> Response {
+        Json(self.to_json()).into_response()
+    }
+}
+
+/// Layer created by [`Coverage::layer`].
+#[derive(Clone)]
+pub struct CoverageLayer {
+    coverage: Coverage,
+}
+
+impl<S> Layer<S> for CoverageLayer {
+    type Service = CoverageService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CoverageService {
+            inner,
+            coverage: self.coverage.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CoverageService<S> {
+    inner: S,
+    coverage: Coverage,
+}
+
+impl<S> Service<Request<Body>> for CoverageService<S>
+where
+    S: Service<Request<Body>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().clone();
+        let (route, raw_path) = match req.extensions().get::<MatchedPath>() {
+            Some(matched) => (matched.as_str().to_string(), false),
+            None => self.coverage.fallback_route(req.uri().path()),
+        };
+        let fut = self.inner.call(req);
+        let coverage = self.coverage.clone();
+        Box::pin(async move {
+            let response = fut.await?;
+            coverage.record(
+                (method, route, Backend::from_response(&response)),
+                raw_path,
+                start.elapsed(),
+                response.status().is_server_error(),
+            );
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_the_recorded_routes() {
+        let coverage = Coverage::new();
+        let latency = Duration::from_millis(1);
+        for index in 0..MAX_ROUTES + 10 {
+            let path = format!("/random/{index}");
+            coverage.record((Method::GET, path, Backend::Asgi), true, latency, false);
+        }
+        for method in ["PURGE", "BREW", "SPAM"] {
+            let method = Method::from_bytes(method.as_bytes()).unwrap();
+            let route = "/users".to_string();
+            coverage.record((method, route, Backend::Rust), false, latency, false);
+        }
+
+        let report = coverage.report();
+        assert_eq!(report.routes.len(), MAX_ROUTES + 2);
+        let find = |route: &str| report.routes.iter().find(|r| r.route == route).unwrap();
+        assert_eq!(find("/users").method.as_str(), OTHER_METHOD);
+        assert_eq!(find("/users").requests, 3);
+        let fallback = find(FALLBACK_ROUTE);
+        assert_eq!(fallback.requests, 10);
+    }
+}
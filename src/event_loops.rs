@@ -0,0 +1,134 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread::JoinHandle,
+};
+
+use pyo3::{exceptions::PyRuntimeError, prelude::*, types::PyDict};
+use pyo3_async_runtimes::TaskLocals;
+
+use crate::interface::AsgiInterface;
+
+/// An extra asyncio event loop, running on its own thread
+pub(crate) struct EventLoop {
+    event_loop: PyObject,
+    thread: JoinHandle<()>,
+    pub(crate) locals: Arc<TaskLocals>,
+    /// The applications to run on this loop, in the same order as the server context's
+    pub(crate) apps: Vec<PyObject>,
+}
+
+impl EventLoop {
+    /// Start a new event loop thread. With a `factory`, it's called on the new thread (with the
+    /// loop set as the current event loop) with the name of each app, to create the copy of the
+    /// app that runs on this loop. Without it, the loop shares the `apps`.
+    pub(crate) fn start(
+        py: Python<'_>,
+        index: usize,
+        apps: &[(String, PyObject)],
+        factory: Option<&PyObject>,
+        interface: AsgiInterface,
+    ) -> PyResult<EventLoop> {
+        let names = apps
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        let shared = apps
+            .iter()
+            .map(|(_, app)| app.clone_ref(py))
+            .collect::<Vec<_>>();
+        let factory = factory.map(|factory| factory.clone_ref(py));
+        let (started_tx, started_rx) = mpsc::channel();
+
+        let thread = std::thread::Builder::new()
+            .name(format!("parviocula-loop-{index}"))
+            .spawn(move || {
+                Python::with_gil(|py| {
+                    let started = (|| {
+                        let asyncio = py.import("asyncio")?;
+                        let event_loop = asyncio.call_method0("new_event_loop")?;
+                        asyncio.call_method1("set_event_loop", (&event_loop,))?;
+                        let apps = match &factory {
+                            Some(factory) => names
+                                .iter()
+                                .map(|name| interface.adapt(py, factory.call1(py, (name,))?))
+                                .collect::<PyResult<Vec<_>>>()?,
+                            None => shared,
+                        };
+                        let locals = TaskLocals::new(event_loop.clone()).copy_context(py)?;
+                        Ok::<_, PyErr>((event_loop, apps, locals))
+                    })();
+                    match started {
+                        Ok((event_loop, apps, locals)) => {
+                            let _ =
+                                started_tx.send(Ok((event_loop.clone().unbind(), apps, locals)));
+                            if let Err(_e) = event_loop.call_method0("run_forever") {
+                                #[cfg(feature = "tracing")]
+                                tracing::error!("event loop {index} failed: {_e}");
+                            }
+                            let _ = event_loop.call_method0("close");
+                        }
+                        Err(e) => {
+                            let _ = started_tx.send(Err(e));
+                        }
+                    }
+                })
+            })?;
+
+        let (event_loop, apps, locals) = py
+            .allow_threads(move || started_rx.recv())
+            .map_err(|_| PyErr::new::<PyRuntimeError, _>("event loop thread exited"))??;
+        Ok(EventLoop {
+            event_loop,
+            thread,
+            locals: Arc::new(locals),
+            apps,
+        })
+    }
+
+    /// Ask the loop to stop, without waiting for its thread
+    pub(crate) fn stop_soon(&self, py: Python<'_>) -> PyResult<()> {
+        let event_loop = self.event_loop.bind(py);
+        event_loop.call_method1("call_soon_threadsafe", (event_loop.getattr("stop")?,))?;
+        Ok(())
+    }
+
+    /// Stop the loop and wait for its thread to finish
+    pub(crate) async fn stop(self) {
+        if let Err(_e) = Python::with_gil(|py| self.stop_soon(py)) {
+            #[cfg(feature = "tracing")]
+            tracing::error!("failed to stop event loop: {_e}");
+        }
+        let thread = self.thread;
+        let _ = tokio::task::spawn_blocking(move || thread.join()).await;
+    }
+}
+
+/// One copy of an application, on one event loop
+pub(crate) struct LoopApp {
+    pub(crate) app: Arc<PyObject>,
+    pub(crate) locals: Arc<TaskLocals>,
+    pub(crate) state: Arc<Py<PyDict>>,
+}
+
+/// The copies of an application on every event loop, which requests are spread across in turn
+pub(crate) struct EventLoopApps {
+    apps: Vec<LoopApp>,
+    next: AtomicUsize,
+}
+
+impl EventLoopApps {
+    pub(crate) fn new(apps: Vec<LoopApp>) -> EventLoopApps {
+        EventLoopApps {
+            apps,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn next(&self) -> &LoopApp {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        &self.apps[index % self.apps.len()]
+    }
+}
@@ -0,0 +1,84 @@
+use std::{borrow::Cow, marker::PhantomData};
+
+use axum::http::Extensions;
+use pyo3::{prelude::*, types::PyDict};
+
+/// Converts request extensions, e.g. set by rust middleware, into entries of the ASGI scope.
+///
+/// `state` becomes `scope["state"]` (i.e. `request.state` in Starlette) and `extensions`
+/// becomes `scope["extensions"]`. Either is only added to the scope if it isn't empty.
+///
+/// Closures with the same signature as [`ExtensionsConverter::convert`] implement this trait,
+/// and [`ExtensionToState`] covers the common case of copying a single extension.
+pub trait ExtensionsConverter: Send + Sync + 'static {
+    fn convert<'py>(
+        &self,
+        py: Python<'py>,
+        request: &Extensions,
+        state: &Bound<'py, PyDict>,
+        extensions: &Bound<'py, PyDict>,
+    ) -> PyResult<()>;
+}
+
+impl<F> ExtensionsConverter for F
+where
+    F: for<'py> Fn(
+            Python<'py>,
+            &Extensions,
+            &Bound<'py, PyDict>,
+            &Bound<'py, PyDict>,
+        ) -> PyResult<()>
+        + Send
+        + Sync
+        + 'static,
+{
+    fn convert<'py>(
+        &self,
+        py: Python<'py>,
+        request: &Extensions,
+        state: &Bound<'py, PyDict>,
+        extensions: &Bound<'py, PyDict>,
+    ) -> PyResult<()> {
+        self(py, request, state, extensions)
+    }
+}
+
+/// Copies the request extension of type `T`, if present, into `scope["state"][key]`.
+///
+/// ```rust,ignore
+/// #[derive(Clone, IntoPyObject)]
+/// struct User { id: u64, name: String }
+///
+/// let asgi = asgi.with_extensions_converter(ExtensionToState::<User>::new("user"));
+/// ```
+pub struct ExtensionToState<T> {
+    key: Cow<'static, str>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> ExtensionToState<T> {
+    pub fn new(key: impl Into<Cow<'static, str>>) -> ExtensionToState<T> {
+        ExtensionToState {
+            key: key.into(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> ExtensionsConverter for ExtensionToState<T>
+where
+    T: for<'py> IntoPyObject<'py> + Clone + Send + Sync + 'static,
+{
+    fn convert<'py>(
+        &self,
+        _py: Python<'py>,
+        request: &Extensions,
+        state: &Bound<'py, PyDict>,
+        _extensions: &Bound<'py, PyDict>,
+    ) -> PyResult<()> {
+        if let Some(value) = request.get::<T>() {
+            state.set_item(self.key.as_ref(), value.clone())?;
+        }
+        Ok(())
+    }
+}
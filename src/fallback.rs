@@ -0,0 +1,151 @@
+use std::{future::Future, marker::PhantomData, pin::Pin, sync::Arc};
+
+use axum::{
+    body::Body,
+    handler::Handler,
+    http::{Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    asgi::AsgiHandler,
+    request::{buffer_body, copy_request, DEFAULT_MAX_BODY_SIZE},
+};
+
+/// Returned by a rust handler wrapped in [`OrAsgi`] to pass the request on to the ASGI
+/// application instead.
+///
+/// Can be used as a rejection (`Result<impl IntoResponse, NotHandled>`), so handlers that only
+/// support some of an endpoint's behaviour can bail out with `?`. Outside of `OrAsgi` it
+/// responds with a `404 Not Found`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NotHandled;
+
+impl IntoResponse for NotHandled {
+    fn into_response(self) -> Response {
+        let mut response = StatusCode::NOT_FOUND.into_response();
+        response.extensions_mut().insert(NotHandled);
+        response
+    }
+}
+
+impl std::fmt::Display for NotHandled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("not handled")
+    }
+}
+
+impl std::error::Error for NotHandled {}
+
+/// Wraps a rust handler so that requests it declines are replayed to the ASGI application,
+/// without the client noticing.
+///
+/// A request is declined when the handler responds with [`NotHandled`], or with one of the
+/// statuses set with [`OrAsgi::with_fallback_statuses`]. The request body is buffered before
+/// calling the rust handler, so the same body can be sent to the ASGI application. Requests over
+/// the maximum body size, or whose body fails to be read, skip the rust handler.
+///
+/// ```rust,ignore
+/// async fn search(Query(query): Query<HashMap<String, String>>) -> Result<String, NotHandled> {
+///     if query.contains_key("legacy_filter") {
+///         return Err(NotHandled);
+///     }
+///     Ok(...)
+/// }
+///
+/// Router::new().route("/search", get(OrAsgi::new(search, asgi.clone())))
+/// ```
+pub struct OrAsgi<H, T> {
+    rust: H,
+    asgi: AsgiHandler,
+    statuses: Arc<[StatusCode]>,
+    max_body_size: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<H: Clone, T> Clone for OrAsgi<H, T> {
+    fn clone(&self) -> Self {
+        OrAsgi {
+            rust: self.rust.clone(),
+            asgi: self.asgi.clone(),
+            statuses: self.statuses.clone(),
+            max_body_size: self.max_body_size,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<H, T> OrAsgi<H, T> {
+    pub fn new(rust: H, asgi: AsgiHandler) -> OrAsgi<H, T> {
+        OrAsgi {
+            rust,
+            asgi,
+            statuses: Arc::new([]),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Also fall back to the ASGI application when the rust handler responds with any of these
+    /// statuses, e.g. `501 Not Implemented`
+    pub fn with_fallback_statuses<I>(mut self, statuses: I) -> Self
+    where
+        I: IntoIterator<Item = StatusCode>,
+    {
+        self.statuses = statuses.into_iter().collect();
+        self
+    }
+
+    /// Set the maximum size of the request body buffered for the rust handler. Larger requests
+    /// are sent straight to the ASGI application. Defaults to 4MB.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+}
+
+impl<H, T, S> Handler<T, S> for OrAsgi<H, T>
+where
+    H: Handler<T, S>,
+    T: 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    type Future = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+    fn call(self, req: Request<Body>, state: S) -> Self::Future {
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = match buffer_body(body, self.max_body_size).await {
+                Ok(body) => body,
+                Err(body) => {
+                    // the rust handler can't be retried without the body, so the ASGI
+                    // application gets the request as is
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("request body too large to buffer, falling back to ASGI");
+                    let req = Request::from_parts(parts, body);
+                    return Handler::<AsgiHandler, S>::call(self.asgi, req, state).await;
+                }
+            };
+
+            let response = self
+                .rust
+                .call(copy_request(&parts, &body), state.clone())
+                .await;
+            if response.extensions().get::<NotHandled>().is_none()
+                && !self.statuses.contains(&response.status())
+            {
+                return response;
+            }
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                method = %parts.method,
+                path = parts.uri.path(),
+                status = response.status().as_u16(),
+                "rust handler declined request, falling back to ASGI"
+            );
+            let req = Request::from_parts(parts, Body::from(body));
+            Handler::<AsgiHandler, S>::call(self.asgi, req, state).await
+        })
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    convert::Infallible,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{connect_info::ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+
+/// Trusted proxy configuration, equivalent to uvicorn's `--proxy-headers` and
+/// `--forwarded-allow-ips` options.
+///
+/// When the peer address of a connection is one of the trusted proxies, the `client`, `scheme`
+/// and `server` are resolved from the `Forwarded` header, or if that is missing, the
+/// `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers.
+///
+/// The peer address comes from axum's `ConnectInfo<SocketAddr>`, so the server needs to be
+/// started with `into_make_service_with_connect_info::<SocketAddr>()` (or `serve_tls`).
+#[derive(Clone, Debug)]
+pub struct ProxyHeaders {
+    trusted: Arc<Trusted>,
+}
+
+#[derive(Debug)]
+enum Trusted {
+    Any,
+    Networks(Vec<(IpAddr, u8)>),
+}
+
+/// Returned by [`ProxyHeaders::new`] for an entry that isn't an ip address, network or `*`
+#[derive(Debug)]
+pub struct InvalidForwardedAllowIp(String);
+
+impl fmt::Display for InvalidForwardedAllowIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid forwarded allow ip: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidForwardedAllowIp {}
+
+impl ProxyHeaders {
+    /// Create a trusted proxy configuration from a list of ip addresses (e.g. `127.0.0.1`),
+    /// networks (e.g. `10.0.0.0/8`), or `*` to trust all peers.
+    pub fn new<I, T>(forwarded_allow_ips: I) -> Result<ProxyHeaders, InvalidForwardedAllowIp>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let mut networks = Vec::new();
+        for entry in forwarded_allow_ips {
+            let entry = entry.as_ref().trim();
+            if entry == "*" {
+                return Ok(ProxyHeaders {
+                    trusted: Arc::new(Trusted::Any),
+                });
+            }
+            networks
+                .push(parse_network(entry).ok_or_else(|| InvalidForwardedAllowIp(entry.into()))?);
+        }
+        Ok(ProxyHeaders {
+            trusted: Arc::new(Trusted::Networks(networks)),
+        })
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        match self.trusted.as_ref() {
+            Trusted::Any => true,
+            Trusted::Networks(networks) => {
+                let ip = ip.to_canonical();
+                networks
+                    .iter()
+                    .any(|(network, prefix)| in_network(ip, *network, *prefix))
+            }
+        }
+    }
+
+    fn is_trusted_host(&self, host: &str) -> bool {
+        match host.parse::<IpAddr>() {
+            Ok(ip) => self.is_trusted(ip),
+            Err(_) => matches!(self.trusted.as_ref(), Trusted::Any),
+        }
+    }
+
+    /// Pick the client from a list of forwarded hosts, ordered from the original client to the
+    /// last proxy. This is the right most host that isn't a trusted proxy, or the left most
+    /// host if every entry is trusted.
+    fn pick_client<'a>(&self, hosts: &[(&'a str, u16)]) -> Option<(&'a str, u16)> {
+        hosts
+            .iter()
+            .rev()
+            .find(|(host, _)| !self.is_trusted_host(host))
+            .or_else(|| hosts.first())
+            .copied()
+    }
+}
+
+fn parse_network(entry: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = match entry.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (entry, None),
+    };
+    let addr = addr.parse::<IpAddr>().ok()?.to_canonical();
+    let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse::<u8>().ok().filter(|p| *p <= max_prefix)?,
+        None => max_prefix,
+    };
+    Some((addr, prefix))
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// The client, scheme and server of a request, after applying any trusted proxy headers.
+///
+/// This is what the `AsgiHandler` passes on as the `client`, `scheme` and `server` scope
+/// entries. It can be used as an extractor in axum handlers to get the same values, using the
+/// [`ProxyHeaders`] from the request extensions (e.g. `.layer(Extension(proxy_headers))`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForwardedInfo {
+    pub client: Option<(String, u16)>,
+    pub scheme: String,
+    pub server: Option<(String, Option<u16>)>,
+}
+
+impl ForwardedInfo {
+    pub fn from_parts(parts: &Parts, proxy_headers: Option<&ProxyHeaders>) -> ForwardedInfo {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr);
+        #[cfg(feature = "tls")]
+        let is_tls = parts.extensions.get::<crate::tls::TlsInfo>().is_some();
+        #[cfg(not(feature = "tls"))]
+        let is_tls = false;
+        let scheme = match parts.uri.scheme_str() {
+            Some(scheme) => scheme,
+            None if is_tls => "https",
+            None => "http",
+        };
+        let mut info = ForwardedInfo {
+            client: peer.map(|addr| (addr.ip().to_canonical().to_string(), addr.port())),
+            scheme: scheme.to_string(),
+            server: None,
+        };
+
+        if let (Some(proxy_headers), Some(peer)) = (proxy_headers, peer) {
+            if proxy_headers.is_trusted(peer.ip()) {
+                if parts.headers.contains_key("forwarded") {
+                    info.apply_forwarded(&parts.headers, proxy_headers);
+                } else {
+                    info.apply_x_forwarded(&parts.headers, proxy_headers);
+                }
+            }
+        }
+        info
+    }
+
+    /// https://www.rfc-editor.org/rfc/rfc7239
+    fn apply_forwarded(&mut self, headers: &HeaderMap, proxy_headers: &ProxyHeaders) {
+        let elements = headers
+            .get_all("forwarded")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .map(|(key, value)| {
+                        (
+                            key.trim().to_ascii_lowercase(),
+                            value.trim().trim_matches('"'),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let hosts = elements
+            .iter()
+            .filter_map(|element| forwarded_param(element, "for"))
+            .map(split_host_port)
+            .collect::<Vec<_>>();
+        if let Some((host, port)) = proxy_headers.pick_client(&hosts) {
+            self.client = Some((host.to_string(), port));
+        }
+        if let Some(last) = elements.last() {
+            if let Some(proto) = forwarded_param(last, "proto") {
+                self.scheme = proto.to_ascii_lowercase();
+            }
+            if let Some(host) = forwarded_param(last, "host") {
+                self.server = Some(self.server_from_host(host, None));
+            }
+        }
+    }
+
+    fn apply_x_forwarded(&mut self, headers: &HeaderMap, proxy_headers: &ProxyHeaders) {
+        let last_value = |name: &str| {
+            headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
+                .rfind(|value| !value.is_empty())
+        };
+
+        let hosts = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|host| (host, 0))
+            .collect::<Vec<_>>();
+        if let Some((host, port)) = proxy_headers.pick_client(&hosts) {
+            self.client = Some((host.to_string(), port));
+        }
+        if let Some(proto) = last_value("x-forwarded-proto") {
+            self.scheme = proto.to_ascii_lowercase();
+        }
+        if let Some(host) = last_value("x-forwarded-host") {
+            let port = last_value("x-forwarded-port").and_then(|port| port.parse().ok());
+            self.server = Some(self.server_from_host(host, port));
+        }
+    }
+
+    fn server_from_host(&self, host: &str, port: Option<u16>) -> (String, Option<u16>) {
+        let (host, host_port) = split_host_port(host);
+        let default_port = match self.scheme.as_str() {
+            "https" | "wss" => 443,
+            _ => 80,
+        };
+        let port = port
+            .or((host_port != 0).then_some(host_port))
+            .unwrap_or(default_port);
+        (host.to_string(), Some(port))
+    }
+}
+
+fn forwarded_param<'a>(element: &[(String, &'a str)], key: &str) -> Option<&'a str> {
+    element
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| *value)
+}
+
+/// Split `host:port`, `[v6]:port`, or a bare host into its parts, using `0` for a missing port
+fn split_host_port(value: &str) -> (&str, u16) {
+    if let Some(rest) = value.strip_prefix('[') {
+        if let Some((host, rest)) = rest.split_once(']') {
+            let port = rest.strip_prefix(':').and_then(|p| p.parse().ok());
+            return (host, port.unwrap_or(0));
+        }
+    }
+    match value.rsplit_once(':') {
+        // a bare ipv6 address has more than one ':'
+        Some((host, port)) if !host.contains(':') => (host, port.parse().unwrap_or(0)),
+        _ => (value, 0),
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ForwardedInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let proxy_headers = parts.extensions.get::<ProxyHeaders>();
+        Ok(ForwardedInfo::from_parts(parts, proxy_headers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    fn parts(peer: &str, headers: &[(&str, &str)]) -> Parts {
+        let mut req = Request::builder().uri("/");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let (mut parts, _) = req.body(()).unwrap().into_parts();
+        parts
+            .extensions
+            .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        parts
+    }
+
+    fn proxy_headers() -> ProxyHeaders {
+        ProxyHeaders::new(["127.0.0.1", "10.0.0.0/8"]).unwrap()
+    }
+
+    #[test]
+    fn parses_allow_ips() {
+        assert!(ProxyHeaders::new(["127.0.0.1", "10.0.0.0/8", "::1", "fd00::/8"]).is_ok());
+        assert!(ProxyHeaders::new(["10.0.0.0/33"]).is_err());
+        assert!(ProxyHeaders::new(["localhost"]).is_err());
+        let proxy_headers = proxy_headers();
+        assert!(proxy_headers.is_trusted("10.1.2.3".parse().unwrap()));
+        assert!(proxy_headers.is_trusted("::ffff:127.0.0.1".parse().unwrap()));
+        assert!(!proxy_headers.is_trusted("11.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn ignores_headers_without_proxy_headers() {
+        let parts = parts("1.2.3.4:5000", &[("x-forwarded-for", "9.9.9.9")]);
+        let info = ForwardedInfo::from_parts(&parts, None);
+        assert_eq!(info.client, Some(("1.2.3.4".to_string(), 5000)));
+        assert_eq!(info.scheme, "http");
+        assert_eq!(info.server, None);
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peers() {
+        let parts = parts(
+            "1.2.3.4:5000",
+            &[
+                ("x-forwarded-for", "9.9.9.9"),
+                ("x-forwarded-proto", "https"),
+            ],
+        );
+        let info = ForwardedInfo::from_parts(&parts, Some(&proxy_headers()));
+        assert_eq!(info.client, Some(("1.2.3.4".to_string(), 5000)));
+        assert_eq!(info.scheme, "http");
+    }
+
+    #[test]
+    fn applies_x_forwarded_from_trusted_peers() {
+        let parts = parts(
+            "10.0.0.1:5000",
+            &[
+                ("x-forwarded-for", "6.6.6.6, 9.9.9.9, 10.0.0.2"),
+                ("x-forwarded-proto", "HTTPS"),
+                ("x-forwarded-host", "example.com"),
+            ],
+        );
+        let info = ForwardedInfo::from_parts(&parts, Some(&proxy_headers()));
+        // the right most untrusted host, as the left ones can be spoofed by the client
+        assert_eq!(info.client, Some(("9.9.9.9".to_string(), 0)));
+        assert_eq!(info.scheme, "https");
+        assert_eq!(info.server, Some(("example.com".to_string(), Some(443))));
+    }
+
+    #[test]
+    fn applies_forwarded_before_x_forwarded() {
+        let parts = parts(
+            "127.0.0.1:5000",
+            &[
+                (
+                    "forwarded",
+                    "for=9.9.9.9;proto=http, for=\"[2001:db8::1]:4711\";proto=https;host=example.com:8443",
+                ),
+                ("x-forwarded-for", "6.6.6.6"),
+            ],
+        );
+        let info = ForwardedInfo::from_parts(&parts, Some(&proxy_headers()));
+        assert_eq!(info.client, Some(("2001:db8::1".to_string(), 4711)));
+        assert_eq!(info.scheme, "https");
+        assert_eq!(info.server, Some(("example.com".to_string(), Some(8443))));
+    }
+
+    #[test]
+    fn trusts_every_hop_with_wildcard() {
+        let proxy_headers = ProxyHeaders::new(["*"]).unwrap();
+        let parts = parts("1.2.3.4:5000", &[("x-forwarded-for", "9.9.9.9, 8.8.8.8")]);
+        let info = ForwardedInfo::from_parts(&parts, Some(&proxy_headers));
+        assert_eq!(info.client, Some(("9.9.9.9".to_string(), 0)));
+    }
+
+    #[test]
+    fn splits_host_and_port() {
+        assert_eq!(split_host_port("example.com:80"), ("example.com", 80));
+        assert_eq!(split_host_port("example.com"), ("example.com", 0));
+        assert_eq!(split_host_port("[::1]:8080"), ("::1", 8080));
+        assert_eq!(split_host_port("::1"), ("::1", 0));
+    }
+}
@@ -0,0 +1,218 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{FromRequestParts, RawPathParams},
+    handler::Handler,
+    http::{HeaderName, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use pyo3::{
+    exceptions::PyRuntimeError,
+    prelude::*,
+    types::{PyBytes, PyDict},
+};
+
+use crate::{
+    asgi::{AsgiHandler, Backend},
+    request::{body_error_status, DEFAULT_MAX_BODY_SIZE},
+};
+
+/// The request passed to functions mounted with [`PyFunctionHandler`]
+#[pyclass(frozen, name = "Request")]
+struct PyRequest {
+    #[pyo3(get)]
+    method: String,
+    #[pyo3(get)]
+    path: String,
+    #[pyo3(get)]
+    query_string: String,
+    /// The headers as `(name, value)` pairs, with lowercase names
+    #[pyo3(get)]
+    headers: Vec<(String, String)>,
+    path_params: Vec<(String, String)>,
+    body: Bytes,
+}
+
+#[pymethods]
+impl PyRequest {
+    /// The parameters captured by the axum route, e.g. `{"id": "1"}` for `/users/{id}`
+    #[getter]
+    fn path_params<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let params = PyDict::new(py);
+        for (name, value) in &self.path_params {
+            params.set_item(name, value)?;
+        }
+        Ok(params)
+    }
+
+    #[getter]
+    fn body<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.body)
+    }
+
+    /// The first value of a header, or `None`
+    fn header(&self, name: &str) -> Option<String> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Request {} {}>", self.method, self.path)
+    }
+}
+
+/// An axum handler calling a single python coroutine function, skipping the routing, middleware
+/// and ASGI messages of a full ASGI application. Meant for hot endpoints.
+///
+/// The function is called with a `Request` (with `method`, `path`, `query_string`, `headers`,
+/// `path_params`, `body` and `header(name)`) and returns a `(status, headers, body)` tuple,
+/// where the body is `bytes` or `str`.
+///
+/// ```python
+/// async def get_user(request):
+///     user = await load_user(request.path_params["id"])
+///     return 200, [("content-type", "application/json")], json.dumps(user)
+/// ```
+///
+/// ```rust,ignore
+/// let get_user = Python::with_gil(|py| -> PyResult<PyObject> {
+///     Ok(py.import("app")?.getattr("get_user")?.unbind())
+/// })?;
+/// Router::new()
+///     .route("/users/{id}", get(PyFunctionHandler::from_asgi(&asgi, Arc::new(get_user))))
+///     .fallback(asgi)
+/// ```
+///
+/// Responses are marked as served by python with [`Backend::Asgi`].
+#[derive(Clone)]
+pub struct PyFunctionHandler {
+    function: Arc<PyObject>,
+    locals: FunctionLocals,
+}
+
+#[derive(Clone)]
+enum FunctionLocals {
+    Fixed(Arc<pyo3_async_runtimes::TaskLocals>),
+    Asgi(AsgiHandler),
+}
+
+impl PyFunctionHandler {
+    /// Call the function on the event loop of the given locals
+    pub fn new(
+        function: Arc<PyObject>,
+        locals: Arc<pyo3_async_runtimes::TaskLocals>,
+    ) -> PyFunctionHandler {
+        PyFunctionHandler {
+            function,
+            locals: FunctionLocals::Fixed(locals),
+        }
+    }
+
+    /// Call the function on the event loops of the `AsgiHandler`, taking turns with the ASGI
+    /// application when it runs on several (see `ServerContext::set_event_loops`). The same
+    /// function is called on every loop.
+    pub fn from_asgi(asgi: &AsgiHandler, function: Arc<PyObject>) -> PyFunctionHandler {
+        PyFunctionHandler {
+            function,
+            locals: FunctionLocals::Asgi(asgi.clone()),
+        }
+    }
+}
+
+type PyResponse = (u16, Vec<(String, String)>, Bytes);
+
+fn extract_response(result: &Bound<'_, PyAny>) -> PyResult<PyResponse> {
+    let (status, headers, body): (u16, Vec<(String, String)>, Bound<'_, PyAny>) =
+        result.extract()?;
+    let body = match body.extract::<String>() {
+        Ok(body) => Bytes::from(body),
+        Err(_) => Bytes::from(body.extract::<Vec<u8>>()?),
+    };
+    Ok((status, headers, body))
+}
+
+fn build_response((status, headers, body): PyResponse) -> Result<Response, &'static str> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = StatusCode::from_u16(status).map_err(|_| "invalid status")?;
+    for (name, value) in headers {
+        response.headers_mut().append(
+            HeaderName::from_bytes(name.as_bytes()).map_err(|_| "invalid header name")?,
+            HeaderValue::from_str(&value).map_err(|_| "invalid header value")?,
+        );
+    }
+    Ok(response)
+}
+
+impl<S> Handler<PyFunctionHandler, S> for PyFunctionHandler
+where
+    S: Send + Sync + 'static,
+{
+    type Future = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+    fn call(self, req: Request<Body>, state: S) -> Self::Future {
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            let path_params = match RawPathParams::from_request_parts(&mut parts, &state).await {
+                Ok(params) => params
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            let body = match to_bytes(body, DEFAULT_MAX_BODY_SIZE).await {
+                Ok(body) => body,
+                Err(e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!("failed to buffer request body: {e}");
+                    return body_error_status(&e).into_response();
+                }
+            };
+            let request = PyRequest {
+                method: parts.method.to_string(),
+                path: parts.uri.path().to_string(),
+                query_string: parts.uri.query().unwrap_or("").to_string(),
+                headers: parts
+                    .headers
+                    .iter()
+                    .map(|(name, value)| {
+                        (
+                            name.to_string(),
+                            String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                        )
+                    })
+                    .collect(),
+                path_params,
+                body,
+            };
+
+            let locals = match &self.locals {
+                FunctionLocals::Fixed(locals) => locals.clone(),
+                FunctionLocals::Asgi(asgi) => asgi.next_locals(),
+            };
+            let result = async {
+                let coro = Python::with_gil(|py| {
+                    let coro = self.function.call1(py, (Py::new(py, request)?,))?;
+                    pyo3_async_runtimes::into_future_with_locals(&locals, coro.into_bound(py))
+                })?;
+                let result = coro.await?;
+                Python::with_gil(|py| extract_response(result.bind(py)))
+            }
+            .await
+            .and_then(|response| build_response(response).map_err(PyErr::new::<PyRuntimeError, _>));
+
+            let mut response = match result {
+                Ok(response) => response,
+                Err(_e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::error!("error calling python function: {_e}");
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            };
+            response.extensions_mut().insert(Backend::Asgi);
+            response
+        })
+    }
+}
@@ -0,0 +1,78 @@
+use std::{future::Future, sync::Arc};
+
+use futures::future::BoxFuture;
+use pyo3::{prelude::*, types::PyDict, IntoPyObjectExt};
+
+/// The error returned by startup and shutdown hooks
+pub type HookError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The ASGI lifespan `state`, which is shallow copied into the scope of every request.
+///
+/// Startup hooks can add their results (e.g. a handle to a connection pool) to it, before the
+/// python application's own `lifespan.startup` runs. With several ASGI apps, entries are added
+/// to the state of every app.
+#[derive(Clone)]
+pub struct LifespanState {
+    states: Vec<Arc<Py<PyDict>>>,
+}
+
+impl LifespanState {
+    pub(crate) fn new(states: Vec<Arc<Py<PyDict>>>) -> LifespanState {
+        LifespanState { states }
+    }
+
+    /// Add an entry to the state
+    pub fn insert<V>(&self, key: &str, value: V) -> PyResult<()>
+    where
+        V: for<'py> IntoPyObject<'py>,
+    {
+        Python::with_gil(|py| {
+            let value = value.into_bound_py_any(py)?;
+            for state in &self.states {
+                state.bind(py).set_item(key, &value)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+type StartupFn =
+    dyn FnOnce(LifespanState) -> BoxFuture<'static, Result<(), HookError>> + Send + Sync;
+type ShutdownFn = dyn FnOnce() -> BoxFuture<'static, Result<(), HookError>> + Send + Sync;
+
+pub(crate) struct StartupHook {
+    pub(crate) name: String,
+    pub(crate) hook: Box<StartupFn>,
+}
+
+impl StartupHook {
+    pub(crate) fn new<F, Fut>(name: String, hook: F) -> StartupHook
+    where
+        F: FnOnce(LifespanState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HookError>> + Send + 'static,
+    {
+        StartupHook {
+            name,
+            hook: Box::new(move |state| Box::pin(hook(state))),
+        }
+    }
+}
+
+pub(crate) struct ShutdownHook {
+    /// Matches the name of the startup hook creating what this hook releases
+    pub(crate) name: String,
+    pub(crate) hook: Box<ShutdownFn>,
+}
+
+impl ShutdownHook {
+    pub(crate) fn new<F, Fut>(name: String, hook: F) -> ShutdownHook
+    where
+        F: FnOnce() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HookError>> + Send + 'static,
+    {
+        ShutdownHook {
+            name,
+            hook: Box::new(move || Box::pin(hook())),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::{fmt, str::FromStr};
+
+use pyo3::{prelude::*, types::PyDict};
+
+/// The ASGI interface of an application, like uvicorn's `--interface`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AsgiInterface {
+    /// Detect the interface the same way uvicorn does
+    #[default]
+    Auto,
+    /// `await app(scope, receive, send)`
+    Asgi3,
+    /// The legacy double-callable `await app(scope)(receive, send)`
+    Asgi2,
+}
+
+/// Returned when parsing an unknown interface name
+#[derive(Debug)]
+pub struct InvalidAsgiInterface(String);
+
+impl fmt::Display for InvalidAsgiInterface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid ASGI interface {}, expected `auto`, `asgi3` or `asgi2`",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidAsgiInterface {}
+
+impl FromStr for AsgiInterface {
+    type Err = InvalidAsgiInterface;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(AsgiInterface::Auto),
+            "asgi3" => Ok(AsgiInterface::Asgi3),
+            "asgi2" => Ok(AsgiInterface::Asgi2),
+            _ => Err(InvalidAsgiInterface(s.to_string())),
+        }
+    }
+}
+
+impl AsgiInterface {
+    /// Wrap `app` so it can be called as an ASGI3 application, resolving `Auto` first
+    pub fn adapt(self, py: Python<'_>, app: PyObject) -> PyResult<PyObject> {
+        let interface = match self {
+            AsgiInterface::Auto => AsgiInterface::detect(app.bind(py))?,
+            interface => interface,
+        };
+        match interface {
+            AsgiInterface::Asgi2 => Ok(Py::new(py, Asgi2App { app })?.into_any()),
+            _ => Ok(app),
+        }
+    }
+
+    /// Classes are ASGI3 if instances are awaitable, functions and other callables if they're
+    /// coroutine functions. Unlike uvicorn, callables whose `__call__` isn't a python function
+    /// (e.g. implemented in rust or C) are assumed to be ASGI3, as they can't be inspected.
+    fn detect(app: &Bound<'_, PyAny>) -> PyResult<AsgiInterface> {
+        let py = app.py();
+        let inspect = py.import("inspect")?;
+        let is_coroutine_function = |function: &Bound<'_, PyAny>| {
+            inspect
+                .call_method1("iscoroutinefunction", (function,))?
+                .is_truthy()
+        };
+        let is_asgi3 = if inspect.call_method1("isclass", (app,))?.is_truthy()? {
+            app.hasattr("__await__")?
+        } else if inspect.call_method1("isfunction", (app,))?.is_truthy()? {
+            is_coroutine_function(app)?
+        } else {
+            match app.getattr_opt("__call__")? {
+                Some(call) if inspect.call_method1("ismethod", (&call,))?.is_truthy()? => {
+                    is_coroutine_function(&call)?
+                }
+                _ => true,
+            }
+        };
+        Ok(if is_asgi3 {
+            AsgiInterface::Asgi3
+        } else {
+            AsgiInterface::Asgi2
+        })
+    }
+}
+
+/// Adapts an ASGI2 application to the ASGI3 interface
+#[pyclass(frozen)]
+struct Asgi2App {
+    app: PyObject,
+}
+
+#[pymethods]
+impl Asgi2App {
+    fn __call__<'py>(
+        &self,
+        py: Python<'py>,
+        scope: Bound<'py, PyDict>,
+        receive: Bound<'py, PyAny>,
+        send: Bound<'py, PyAny>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let instance = self.app.bind(py).call1((scope,))?;
+        instance.call1((receive, send))
+    }
+}
@@ -0,0 +1,427 @@
+use std::fmt;
+
+use axum::{
+    http::Method,
+    routing::{on, MethodFilter},
+    Router,
+};
+use pyo3::{prelude::*, types::PyString};
+
+use crate::asgi::AsgiHandler;
+
+/// Methods assumed for python routes that accept any method
+const ALL_METHODS: [Method; 7] = [
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+    Method::OPTIONS,
+];
+
+/// A route of the python application, as found on the Starlette (or FastAPI) router.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PythonRoute {
+    /// The path converted to axum's syntax, e.g. `/users/{id:int}` becomes `/users/{id}`
+    pub path: String,
+    /// The methods the route accepts, `None` if it accepts any method
+    pub methods: Option<Vec<Method>>,
+    pub name: Option<String>,
+}
+
+impl PythonRoute {
+    fn methods(&self) -> &[Method] {
+        self.methods.as_deref().unwrap_or(&ALL_METHODS)
+    }
+
+    /// Whether a request path matches the route's path, ignoring the parameter types
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        let mut segments = path.split('/');
+        for pattern in self.path.split('/') {
+            if pattern.starts_with("{*") && pattern.ends_with('}') {
+                return true;
+            }
+            let Some(segment) = segments.next() else {
+                return false;
+            };
+            let is_param = pattern.starts_with('{') && pattern.ends_with('}');
+            if !(segment == pattern || (is_param && !segment.is_empty())) {
+                return false;
+            }
+        }
+        segments.next().is_none()
+    }
+}
+
+/// Find the routes of a Starlette or FastAPI application, following `Mount`s and any
+/// middleware wrapping the application.
+pub fn python_routes(app: &Bound<'_, PyAny>) -> PyResult<Vec<PythonRoute>> {
+    let mut routes = Vec::new();
+    collect_routes(app, "", &mut routes)?;
+    Ok(routes)
+}
+
+fn collect_routes(
+    app: &Bound<'_, PyAny>,
+    prefix: &str,
+    out: &mut Vec<PythonRoute>,
+) -> PyResult<()> {
+    // middleware wraps the application (or the next middleware) in `.app`
+    let mut app = app.clone();
+    while !app.hasattr("routes")? {
+        match app.getattr_opt("app")? {
+            Some(inner) if !inner.is_none() => app = inner,
+            _ => return Ok(()),
+        }
+    }
+
+    for route in app.getattr("routes")?.try_iter()? {
+        let route = route?;
+        let kind = route.get_type().name()?.to_string();
+        if kind == "WebSocketRoute" || kind == "Host" || !route.hasattr("path")? {
+            continue;
+        }
+        let path = format!("{prefix}{}", route.getattr("path")?.extract::<String>()?);
+        if route.hasattr("methods")? {
+            let methods = route.getattr("methods")?;
+            let methods = if methods.is_none() {
+                None
+            } else {
+                let mut methods = methods
+                    .try_iter()?
+                    .map(|method| {
+                        let method = method?;
+                        let method = method.downcast::<PyString>()?.to_str()?;
+                        Method::from_bytes(method.as_bytes()).map_err(|_| {
+                            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                                "invalid method {method}"
+                            ))
+                        })
+                    })
+                    .collect::<PyResult<Vec<_>>>()?;
+                methods.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+                Some(methods)
+            };
+            let name = route
+                .getattr_opt("name")?
+                .filter(|name| !name.is_none())
+                .map(|name| name.extract::<String>())
+                .transpose()?;
+            out.push(PythonRoute {
+                path: to_axum_path(&path),
+                methods,
+                name,
+            });
+        } else if kind == "Mount" {
+            let before = out.len();
+            collect_routes(&route, &path, out)?;
+            if out.len() == before {
+                // a mounted application without routes handles everything under its path
+                // `Mount("/")` has an empty path, which must not become `//{*path}`
+                let base = to_axum_path(&path);
+                out.push(PythonRoute {
+                    path: format!("{}/{{*path}}", base.trim_end_matches('/')),
+                    methods: None,
+                    name: None,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Convert Starlette's `{name}`, `{name:convertor}` and `{name:path}` parameters into axum's
+/// `{name}` and `{*name}`
+fn to_axum_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let param = &rest[start + 1..start + end];
+        match param.split_once(':') {
+            Some((name, "path")) => out.push_str(&format!("{{*{name}}}")),
+            Some((name, _)) => out.push_str(&format!("{{{name}}}")),
+            None => out.push_str(&format!("{{{param}}}")),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    if out.is_empty() {
+        out.push('/');
+    }
+    out
+}
+
+/// The path with its parameter names removed, e.g. `/users/{}` for `/users/{id}`, so paths
+/// naming their parameters differently in python and rust still match
+pub(crate) fn path_shape(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        out.push_str(if rest[start + 1..].starts_with('*') {
+            "{*}"
+        } else {
+            "{}"
+        });
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// A method and path handled by the python application, on a path that also has rust
+/// handlers, that would get a `405 Method Not Allowed` from axum unless it is explicitly
+/// routed to the `AsgiHandler`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MissingAsgiRoute {
+    pub method: Method,
+    /// The path as passed to `Router::route` for the rust handlers
+    pub path: String,
+}
+
+impl fmt::Display for MissingAsgiRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} is handled by python but not routed to the ASGI handler, axum answers 405",
+            self.method, self.path
+        )
+    }
+}
+
+/// Compares the routes of the python application with the routes implemented in rust.
+///
+/// axum doesn't expose the routes of a `Router`, so the rust routes have to be listed
+/// explicitly, as `(method, path)` pairs using the same paths passed to `Router::route`.
+///
+/// ```rust,ignore
+/// let inventory = RouteInventory::new(asgi.python_routes()?, [(Method::GET, "/post_or_get")]);
+/// let app = inventory
+///     .add_asgi_routes(Router::new().route("/post_or_get", get(get_root)), asgi.clone())
+///     .fallback(asgi);
+/// ```
+#[derive(Clone, Debug)]
+pub struct RouteInventory {
+    python: Vec<PythonRoute>,
+    rust: Vec<(Method, String)>,
+}
+
+impl RouteInventory {
+    pub fn new<I, P>(python: Vec<PythonRoute>, rust: I) -> RouteInventory
+    where
+        I: IntoIterator<Item = (Method, P)>,
+        P: Into<String>,
+    {
+        RouteInventory {
+            python,
+            rust: rust
+                .into_iter()
+                .map(|(method, path)| (method, path.into()))
+                .collect(),
+        }
+    }
+
+    pub fn python_routes(&self) -> &[PythonRoute] {
+        &self.python
+    }
+
+    fn is_rust(&self, method: &Method, path: &str) -> bool {
+        let shape = path_shape(path);
+        self.rust.iter().any(|(m, p)| {
+            // axum's `get` also handles `HEAD` requests
+            path_shape(p) == shape && (m == method || (method == Method::HEAD && m == Method::GET))
+        })
+    }
+
+    /// The rust path matching a python path, if any
+    fn rust_path(&self, path: &str) -> Option<&str> {
+        let shape = path_shape(path);
+        self.rust
+            .iter()
+            .find(|(_, p)| path_shape(p) == shape)
+            .map(|(_, p)| p.as_str())
+    }
+
+    fn python_route(&self, path: &str) -> Option<&PythonRoute> {
+        let shape = path_shape(path);
+        self.python
+            .iter()
+            .find(|route| path_shape(&route.path) == shape)
+    }
+
+    /// The `(method, path)` pairs implemented in both rust and python, where rust takes over
+    pub fn overlapping(&self) -> Vec<(Method, String)> {
+        self.python
+            .iter()
+            .flat_map(|route| route.methods().iter().map(move |m| (m, &route.path)))
+            .filter(|(method, path)| self.is_rust(method, path))
+            .map(|(method, path)| (method.clone(), path.clone()))
+            .collect()
+    }
+
+    /// The python routes on paths without any rust handlers, which are served by the router's
+    /// fallback
+    pub fn python_only(&self) -> Vec<&PythonRoute> {
+        self.python
+            .iter()
+            .filter(|route| self.rust_path(&route.path).is_none())
+            .collect()
+    }
+
+    /// The python methods on paths with rust handlers that aren't implemented in rust, which
+    /// axum answers with a `405 Method Not Allowed` unless they're routed to the ASGI handler
+    pub fn missing_asgi_routes(&self) -> Vec<MissingAsgiRoute> {
+        self.python
+            .iter()
+            .filter_map(|route| Some((route, self.rust_path(&route.path)?)))
+            .flat_map(|(route, rust_path)| {
+                route
+                    .methods()
+                    .iter()
+                    .filter(|method| !self.is_rust(method, rust_path))
+                    .map(move |method| MissingAsgiRoute {
+                        method: method.clone(),
+                        path: rust_path.to_string(),
+                    })
+            })
+            .collect()
+    }
+
+    /// The rust `(method, path)` pairs on python paths whose python route doesn't accept the
+    /// method, i.e. requests the python application answers with a `405 Method Not Allowed`
+    /// but rust serves
+    pub fn rust_only_methods(&self) -> Vec<(Method, String)> {
+        self.rust
+            .iter()
+            .filter(|(method, path)| {
+                self.python_route(path)
+                    .is_some_and(|route| !route.methods().contains(method))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Check that none of the python methods on paths with rust handlers are left unrouted
+    pub fn check(&self) -> Result<(), Vec<MissingAsgiRoute>> {
+        let missing = self.missing_asgi_routes();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+
+    /// Add routes sending the python methods on paths with rust handlers to the ASGI
+    /// application (i.e. the `.post(asgi.clone())` from the `mixed_routes` example).
+    ///
+    /// The router must already contain the rust routes.
+    pub fn add_asgi_routes<S>(&self, mut router: Router<S>, asgi: AsgiHandler) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let mut paths: Vec<(String, MethodFilter)> = Vec::new();
+        for missing in self.missing_asgi_routes() {
+            let Ok(filter) = MethodFilter::try_from(missing.method) else {
+                continue;
+            };
+            match paths.iter_mut().find(|(path, _)| *path == missing.path) {
+                Some((_, filters)) => *filters = filters.or(filter),
+                None => paths.push((missing.path, filter)),
+            }
+        }
+        for (path, filter) in paths {
+            router = router.route(&path, on(filter, asgi.clone()));
+        }
+        router
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(path: &str, methods: Option<&[Method]>) -> PythonRoute {
+        PythonRoute {
+            path: path.to_string(),
+            methods: methods.map(<[Method]>::to_vec),
+            name: None,
+        }
+    }
+
+    #[test]
+    fn converts_starlette_paths() {
+        assert_eq!(to_axum_path(""), "/");
+        assert_eq!(to_axum_path("/"), "/");
+        assert_eq!(to_axum_path("/users/{id}"), "/users/{id}");
+        assert_eq!(to_axum_path("/users/{id:int}"), "/users/{id}");
+        assert_eq!(
+            to_axum_path("/users/{id:int}/files/{file:path}"),
+            "/users/{id}/files/{*file}"
+        );
+        assert_eq!(to_axum_path("/broken/{id"), "/broken/{id");
+    }
+
+    #[test]
+    fn ignores_parameter_names() {
+        assert_eq!(path_shape("/users/{id}"), path_shape("/users/{user_id}"));
+        assert_eq!(path_shape("/files/{*path}"), "/files/{*}");
+        assert_ne!(path_shape("/files/{*path}"), path_shape("/files/{path}"));
+    }
+
+    #[test]
+    fn matches_request_paths() {
+        assert!(route("/", None).matches("/"));
+        assert!(!route("/", None).matches("/users"));
+        assert!(route("/users/{id}", None).matches("/users/1"));
+        assert!(!route("/users/{id}", None).matches("/users/"));
+        assert!(!route("/users/{id}", None).matches("/users/1/files"));
+        assert!(route("/files/{*path}", None).matches("/files/a/b"));
+        assert!(!route("/files/{*path}", None).matches("/other/a"));
+    }
+
+    #[test]
+    fn compares_routes() {
+        let inventory = RouteInventory::new(
+            vec![
+                route("/users/{id}", Some(&[Method::GET, Method::DELETE])),
+                route("/orders", Some(&[Method::GET])),
+                route("/health", None),
+            ],
+            [
+                (Method::GET, "/users/{user_id}"),
+                (Method::GET, "/orders"),
+                (Method::POST, "/orders"),
+            ],
+        );
+        assert_eq!(
+            inventory.overlapping(),
+            vec![
+                (Method::GET, "/users/{id}".to_string()),
+                (Method::GET, "/orders".to_string()),
+            ]
+        );
+        assert_eq!(inventory.python_only(), vec![&route("/health", None)]);
+        assert_eq!(
+            inventory.missing_asgi_routes(),
+            vec![MissingAsgiRoute {
+                method: Method::DELETE,
+                path: "/users/{user_id}".to_string(),
+            }]
+        );
+        assert!(inventory.check().is_err());
+        assert_eq!(
+            inventory.rust_only_methods(),
+            vec![(Method::POST, "/orders".to_string())]
+        );
+    }
+}
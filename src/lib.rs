@@ -1,15 +1,79 @@
+mod access_log;
 mod asgi;
+mod coverage;
+mod event_loops;
+mod extensions;
+mod fallback;
+mod forwarded;
+mod function;
+mod hooks;
+mod interface;
+mod inventory;
+mod lifespan;
+mod openapi;
+#[cfg(feature = "proxy")]
+mod proxy;
+#[cfg(feature = "record")]
+mod record;
+mod request;
+mod request_trace;
+mod routing_table;
+mod rust_app;
+mod shadow;
+mod split;
+mod state;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "opentelemetry")]
+mod trace_context;
+#[cfg(all(feature = "workers", unix))]
+mod workers;
+mod wsgi;
 
 use std::future::Future;
 use std::sync::Arc;
 
 use futures::future::BoxFuture;
-use pyo3::exceptions::PyRuntimeError;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyString};
+use pyo3::types::PyDict;
 use tokio::sync::{mpsc, oneshot, Mutex};
 
-pub use crate::asgi::AsgiHandler;
+use crate::event_loops::{EventLoop, EventLoopApps, LoopApp};
+use crate::hooks::{ShutdownHook, StartupHook};
+use crate::lifespan::Lifespan;
+
+pub use crate::access_log::{AccessLog, AccessLogLayer};
+pub use crate::asgi::{AsgiCallError, AsgiHandler, Backend};
+pub use crate::coverage::{
+    Coverage, CoverageLayer, CoverageReport, CoverageService, RouteCoverage,
+};
+pub use crate::extensions::{ExtensionToState, ExtensionsConverter};
+pub use crate::fallback::{NotHandled, OrAsgi};
+pub use crate::forwarded::{ForwardedInfo, InvalidForwardedAllowIp, ProxyHeaders};
+pub use crate::function::PyFunctionHandler;
+pub use crate::hooks::{HookError, LifespanState};
+pub use crate::interface::{AsgiInterface, InvalidAsgiInterface};
+pub use crate::inventory::{python_routes, MissingAsgiRoute, PythonRoute, RouteInventory};
+pub use crate::openapi::{OpenApi, OpenApiError};
+#[cfg(feature = "proxy")]
+pub use crate::proxy::{InvalidUpstream, ProxyHandler};
+#[cfg(feature = "record")]
+pub use crate::record::{Fixture, InvalidFixture, Recorder, Replay, ReplayMismatch};
+pub use crate::routing_table::{
+    InvalidRoutingTable, RoutingTable, RoutingTableLayer, RoutingTableService,
+};
+pub use crate::rust_app::RustApp;
+pub use crate::shadow::{Shadow, ShadowDiff};
+pub use crate::split::{Split, SplitControl};
+pub use crate::state::{ScopeState, StatefulAsgiHandler};
+#[cfg(feature = "tls")]
+pub use crate::tls::{serve_tls, TlsInfo};
+#[cfg(feature = "opentelemetry")]
+pub use crate::trace_context::{TraceContext, TraceContextPropagation};
+#[cfg(all(feature = "workers", unix))]
+pub use crate::workers::{AsgiWorkerPool, AsgiWorkers};
+pub use crate::wsgi::WsgiHandler;
 
 #[pyclass]
 struct Receiver {
@@ -73,6 +137,57 @@ where
     }
 }
 
+pub trait MultiAsyncFn {
+    fn call(&self, apps: AsgiApps, rx: oneshot::Receiver<()>) -> BoxFuture<'static, ()>;
+}
+
+impl<T, F> MultiAsyncFn for T
+where
+    T: Fn(AsgiApps, oneshot::Receiver<()>) -> F,
+    F: Future<Output = ()> + Send + 'static,
+{
+    fn call(&self, apps: AsgiApps, rx: oneshot::Receiver<()>) -> BoxFuture<'static, ()> {
+        Box::pin(self(apps, rx))
+    }
+}
+
+enum Server {
+    Single(Box<dyn AsyncFn + Send + Sync>),
+    Multi(Box<dyn MultiAsyncFn + Send + Sync>),
+}
+
+/// The `AsgiHandler`s of the apps passed to [`create_multi_server_context`], by name.
+#[derive(Clone)]
+pub struct AsgiApps {
+    handlers: Vec<(String, AsgiHandler)>,
+}
+
+impl AsgiApps {
+    pub fn get(&self, name: &str) -> Option<&AsgiHandler> {
+        self.handlers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, handler)| handler)
+    }
+
+    /// The names and handlers, in the order the apps were passed in
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &AsgiHandler)> {
+        self.handlers
+            .iter()
+            .map(|(name, handler)| (name.as_str(), handler))
+    }
+}
+
+impl std::ops::Index<&str> for AsgiApps {
+    type Output = AsgiHandler;
+
+    /// Panics if there's no app with the given name
+    fn index(&self, name: &str) -> &AsgiHandler {
+        self.get(name)
+            .unwrap_or_else(|| panic!("no ASGI app named {name}"))
+    }
+}
+
 #[pymethods]
 impl Sender {
     fn __call__<'a>(&'a mut self, py: Python<'a>, args: Py<PyDict>) -> PyResult<Bound<'a, PyAny>> {
@@ -98,12 +213,154 @@ pub struct ServerContext {
     trigger_shutdown_rx: Option<oneshot::Receiver<()>>,
     wait_shutdown_tx: Option<oneshot::Sender<()>>,
     wait_shutdown_rx: Option<oneshot::Receiver<()>>,
-    app: Option<PyObject>,
-    server: Option<Box<dyn AsyncFn + Send + Sync>>,
+    apps: Option<Vec<(String, PyObject)>>,
+    server: Option<Server>,
+    interface: AsgiInterface,
+    event_loops: usize,
+    app_factory: Option<PyObject>,
+    routing_table: Option<RoutingTable>,
+    coverage: Option<Coverage>,
+    rust_app: Option<RustApp>,
+    startup_hooks: Vec<StartupHook>,
+    shutdown_hooks: Vec<ShutdownHook>,
+}
+
+impl ServerContext {
+    /// Expose a routing table to python through the `set_routes` and `get_routes` methods
+    pub fn set_routing_table(&mut self, routing_table: RoutingTable) {
+        self.routing_table = Some(routing_table);
+    }
+
+    fn routing_table(&self) -> PyResult<&RoutingTable> {
+        self.routing_table
+            .as_ref()
+            .ok_or_else(|| PyErr::new::<PyRuntimeError, _>("No routing table configured"))
+    }
+
+    /// Expose coverage statistics to python through the `coverage` and `reset_coverage` methods
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    fn get_coverage(&self) -> PyResult<&Coverage> {
+        self.coverage
+            .as_ref()
+            .ok_or_else(|| PyErr::new::<PyRuntimeError, _>("No coverage configured"))
+    }
+
+    /// Set the ASGI interface of the applications, detected automatically by default
+    pub fn set_asgi_interface(&mut self, interface: AsgiInterface) {
+        self.interface = interface;
+    }
+
+    /// Run the applications on `count` asyncio event loops, spreading requests across them, so
+    /// a python handler blocking its loop doesn't stall every request. The extra loops run on
+    /// their own threads, next to the loop `start` is awaited on.
+    ///
+    /// Without a `factory` every loop runs the same application objects. Their lifespan only
+    /// runs once, on the loop `start` is awaited on, and its state is shared by every loop, so
+    /// the lifespan must not create loop specific resources (e.g. connection pools). With a
+    /// factory, it's called on each extra loop's thread with the name of each app (`"app"` for
+    /// a single app) to create that loop's copy, and every copy runs its own lifespan with its
+    /// own state. Subinterpreters aren't supported, so all loops still share the GIL.
+    pub fn set_event_loops(&mut self, count: usize, factory: Option<PyObject>) {
+        self.event_loops = count.max(1);
+        self.app_factory = factory;
+    }
+
+    /// Run `hook` when the server starts, before the python application's `lifespan.startup`.
+    ///
+    /// Startup hooks run in the order they're added, and can add their results to the lifespan
+    /// state passed to python. If a hook fails, the server doesn't start and `start` raises a
+    /// `RuntimeError` naming the hook, after running the shutdown hooks added with the same name
+    /// as the hooks that already started. If the python application fails to start, every
+    /// shutdown hook runs.
+    pub fn add_startup_hook<F, Fut>(&mut self, name: impl Into<String>, hook: F)
+    where
+        F: FnOnce(LifespanState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HookError>> + Send + 'static,
+    {
+        self.startup_hooks.push(StartupHook::new(name.into(), hook));
+    }
+
+    /// Run `hook` when the server shuts down, after the python application's
+    /// `lifespan.shutdown`.
+    ///
+    /// Shutdown hooks run in the reverse order they're added, so resources are released in the
+    /// reverse order of their creation. A failing hook doesn't stop the remaining hooks.
+    pub fn add_shutdown_hook<F, Fut>(&mut self, name: impl Into<String>, hook: F)
+    where
+        F: FnOnce() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HookError>> + Send + 'static,
+    {
+        self.shutdown_hooks
+            .push(ShutdownHook::new(name.into(), hook));
+    }
+
+    /// Expose the rust router to python as an ASGI application through the `rust_app` method
+    pub fn set_rust_app(&mut self, rust_app: RustApp) {
+        self.rust_app = Some(rust_app);
+    }
 }
 
 #[pymethods]
 impl ServerContext {
+    /// Replace the routes of the routing table, using the `RoutingTable` text format
+    fn set_routes(&self, routes: &str) -> PyResult<()> {
+        self.routing_table()?
+            .update_from_str(routes)
+            .map_err(|e| PyErr::new::<PyValueError, _>(e.to_string()))
+    }
+
+    /// The current routes of the routing table as `(method, path, backend)` tuples
+    fn get_routes(&self) -> PyResult<Vec<(String, String, &'static str)>> {
+        Ok(self
+            .routing_table()?
+            .routes()
+            .into_iter()
+            .map(|(method, path, backend)| {
+                (
+                    method.map_or_else(|| "*".to_string(), |method| method.to_string()),
+                    path,
+                    backend.as_str(),
+                )
+            })
+            .collect())
+    }
+
+    /// The coverage statistics per method, route and backend as dicts, ordered by the number of
+    /// requests
+    fn coverage<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.get_coverage()?.report().to_dicts(py)
+    }
+
+    fn reset_coverage(&self) -> PyResult<()> {
+        self.get_coverage()?.reset();
+        Ok(())
+    }
+
+    /// Set the ASGI interface of the applications: `auto` (the default), `asgi3` or `asgi2`
+    fn set_interface(&mut self, interface: &str) -> PyResult<()> {
+        let interface = interface
+            .parse()
+            .map_err(|e: InvalidAsgiInterface| PyErr::new::<PyValueError, _>(e.to_string()))?;
+        self.set_asgi_interface(interface);
+        Ok(())
+    }
+
+    /// Run the applications on `count` event loops, see [`ServerContext::set_event_loops`]
+    #[pyo3(name = "set_event_loops", signature = (count, factory=None))]
+    fn py_set_event_loops(&mut self, count: usize, factory: Option<PyObject>) {
+        self.set_event_loops(count, factory);
+    }
+
+    /// An ASGI application dispatching requests into the rust router, see [`RustApp`]
+    fn rust_app(&self) -> PyResult<RustApp> {
+        self.rust_app
+            .clone()
+            .ok_or_else(|| PyErr::new::<PyRuntimeError, _>("No rust app configured"))
+    }
+
     fn shutdown<'a>(&'a mut self, py: Python<'a>) -> PyResult<Bound<'a, PyAny>> {
         if let (Some(tx), Some(rx)) = (
             self.trigger_shutdown_tx.take(),
@@ -129,100 +386,180 @@ impl ServerContext {
 
     fn start<'a>(&'a mut self, py: Python<'a>) -> PyResult<Bound<'a, PyAny>> {
         match (
-            self.trigger_shutdown_rx.take(),
-            self.app.take(),
-            self.server.take(),
-            self.wait_shutdown_tx.take(),
+            &self.trigger_shutdown_rx,
+            &self.apps,
+            &self.server,
+            &self.wait_shutdown_tx,
         ) {
-            (Some(rx), Some(app), Some(server), Some(tx)) => {
+            (Some(_), Some(apps), Some(_), Some(_)) => {
+                // nothing is taken until the steps that can fail are done, so `start` can be
+                // retried after an error
                 let locals = Arc::new(
                     pyo3_async_runtimes::TaskLocals::with_running_loop(py)?.copy_context(py)?,
                 );
-                let (lifespan_receiver, lifespan_receiver_tx) = Receiver::new();
-                let (lifespan_sender, mut lifespan_sender_rx) = Sender::new(locals.clone());
-                //let (ready_tx, ready_rx) = oneshot::channel::<()>();
+                let apps = apps
+                    .iter()
+                    .map(|(name, app)| {
+                        Ok((name.clone(), self.interface.adapt(py, app.clone_ref(py))?))
+                    })
+                    .collect::<PyResult<Vec<_>>>()?;
+                let mut event_loops = Vec::with_capacity(self.event_loops - 1);
+                for index in 1..self.event_loops {
+                    let event_loop = EventLoop::start(
+                        py,
+                        index,
+                        &apps,
+                        self.app_factory.as_ref(),
+                        self.interface,
+                    );
+                    match event_loop {
+                        Ok(event_loop) => event_loops.push(event_loop),
+                        Err(e) => {
+                            for event_loop in &event_loops {
+                                let _ = event_loop.stop_soon(py);
+                            }
+                            return Err(e);
+                        }
+                    }
+                }
+                let (Some(rx), Some(server), Some(tx)) = (
+                    self.trigger_shutdown_rx.take(),
+                    self.server.take(),
+                    self.wait_shutdown_tx.take(),
+                ) else {
+                    unreachable!("checked above");
+                };
+                self.apps = None;
+
+                // every app has a copy on every event loop, starting with the current one. Without
+                // a factory they're the same object, which only runs its lifespan once, on the
+                // current loop, and shares its state with every loop
+                let shared = self.app_factory.is_none();
+                let apps = apps
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, (name, app))| {
+                        let state = Arc::new(PyDict::new(py).unbind());
+                        let copies = std::iter::once((app, locals.clone()))
+                            .chain(event_loops.iter().map(|event_loop| {
+                                (
+                                    event_loop.apps[index].clone_ref(py),
+                                    event_loop.locals.clone(),
+                                )
+                            }))
+                            .enumerate()
+                            .map(|(copy, (app, locals))| LoopApp {
+                                app: Arc::new(app),
+                                locals,
+                                state: if shared || copy == 0 {
+                                    state.clone()
+                                } else {
+                                    Arc::new(PyDict::new(py).unbind())
+                                },
+                            })
+                            .collect::<Vec<_>>();
+                        (name, copies)
+                    })
+                    .collect::<Vec<_>>();
+                let lifespans_per_app = if shared { 1 } else { self.event_loops };
+                let states = apps
+                    .iter()
+                    .flat_map(|(_, copies)| copies[..lifespans_per_app].iter())
+                    .map(|copy| copy.state.clone())
+                    .collect::<Vec<_>>();
+                let startup_hooks = std::mem::take(&mut self.startup_hooks);
+                let shutdown_hooks = std::mem::take(&mut self.shutdown_hooks);
 
                 pyo3_async_runtimes::tokio::future_into_py(py, async move {
-                    // https://asgi.readthedocs.io/en/latest/specs/lifespan.html
-                    let lifespan = Python::with_gil(|py| {
-                        let asgi = PyDict::new(py);
-                        asgi.set_item("spec_version", "2.0")?;
-                        asgi.set_item("version", "2.0")?;
-                        let scope = PyDict::new(py);
-                        scope.set_item("type", "lifespan")?;
-                        scope.set_item("asgi", asgi)?;
-
-                        let sender = Py::new(py, lifespan_sender)?;
-                        let receiver = Py::new(py, lifespan_receiver)?;
-                        let args = (scope, receiver, sender);
-                        let res = app.call_method1(py, "__call__", args)?;
-                        let fut = res.extract(py)?;
-                        pyo3_async_runtimes::into_future_with_locals(&locals, fut)
-                    })?;
-
-                    let lifespan_startup = Python::with_gil(|py| {
-                        let scope = PyDict::new(py);
-                        scope.set_item("type", "lifespan.startup")?;
-                        let scope: Py<PyDict> = scope.into();
-                        Ok::<Py<PyDict>, PyErr>(scope)
-                    })?;
-                    if lifespan_receiver_tx.send(lifespan_startup).is_err() {
-                        return Err(PyErr::new::<PyRuntimeError, _>(
-                            "Failed to send lifespan startup",
-                        ));
-                    }
+                    let stop_event_loops = |event_loops: Vec<EventLoop>| async move {
+                        for event_loop in event_loops {
+                            event_loop.stop().await;
+                        }
+                    };
 
-                    // will continue running until the server sends lifespan.shutdown
-                    tokio::spawn(async move {
-                        if let Err(_e) = lifespan.await {
-                            #[cfg(feature = "tracing")]
-                            tracing::error!("Error processing lifespan: {_e}");
+                    let mut started = Vec::with_capacity(startup_hooks.len());
+                    for hook in startup_hooks {
+                        let state = LifespanState::new(states.clone());
+                        if let Err(e) = (hook.hook)(state).await {
+                            stop_event_loops(event_loops).await;
+                            // release what the hooks that already started created
+                            let shutdown_hooks = shutdown_hooks
+                                .into_iter()
+                                .filter(|shutdown| started.contains(&shutdown.name))
+                                .collect();
+                            run_shutdown_hooks(shutdown_hooks).await;
+                            return Err(PyErr::new::<PyRuntimeError, _>(format!(
+                                "startup hook {} failed: {e}",
+                                hook.name
+                            )));
                         }
-                    });
-
-                    if let Some(resp) = lifespan_sender_rx.recv().await {
-                        Python::with_gil(|py| {
-                            let dict: Bound<'_, PyDict> = resp.into_bound(py);
-                            if let Ok(Some(value)) = dict.get_item("type") {
-                                let value: Bound<'_, PyString> = value.downcast_into()?;
-                                let value = value.to_str()?;
-                                if value == "lifespan.startup.complete" {
-                                    return Ok(());
+                        started.push(hook.name);
+                    }
+
+                    let multiple = apps.len() > 1;
+                    let mut lifespans = Vec::with_capacity(states.len());
+                    let mut handlers = Vec::with_capacity(apps.len());
+                    for (name, copies) in apps {
+                        for copy in &copies[..lifespans_per_app] {
+                            match Lifespan::startup(&copy.app, &copy.locals, &copy.state).await {
+                                Ok(lifespan) => lifespans.push(lifespan),
+                                Err(e) => {
+                                    // stop the apps that already started
+                                    for lifespan in lifespans.into_iter().rev() {
+                                        let _ = lifespan.shutdown().await;
+                                    }
+                                    stop_event_loops(event_loops).await;
+                                    run_shutdown_hooks(shutdown_hooks).await;
+                                    return Err(if multiple {
+                                        PyErr::new::<PyRuntimeError, _>(format!(
+                                            "failed to start ASGI app {name}: {e}"
+                                        ))
+                                    } else {
+                                        e
+                                    });
                                 }
                             }
-                            Err(PyErr::new::<PyRuntimeError, _>(
-                                "Failed during asgi startup",
-                            ))
-                        })?;
+                        }
+                        // create asgi service
+                        let LoopApp { app, locals, state } = &copies[0];
+                        let mut handler = AsgiHandler::new_with_locals(app.clone(), locals.clone())
+                            .with_lifespan_state(state.clone());
+                        if copies.len() > 1 {
+                            handler = handler.with_event_loops(EventLoopApps::new(copies));
+                        }
+                        handlers.push((name, handler));
                     }
 
-                    // create asgi service
-                    let asgi_handler = AsgiHandler::new_with_locals(Arc::new(app), locals.clone());
-
-                    server.call(asgi_handler, rx).await;
-
-                    // shutdown
-                    let lifespan_shutdown = Python::with_gil(|py| {
-                        let scope = PyDict::new(py);
-                        scope.set_item("type", "lifespan.shutdown")?;
-                        let scope: Py<PyDict> = scope.into();
-                        Ok::<Py<PyDict>, PyErr>(scope)
-                    })?;
-                    if lifespan_receiver_tx.send(lifespan_shutdown).is_err() {
-                        return Err(PyErr::new::<PyRuntimeError, _>(
-                            "Failed to send lifespan shutdown",
-                        ));
+                    match server {
+                        Server::Single(server) => {
+                            let (_, handler) = handlers.pop().expect("a single ASGI app");
+                            server.call(handler, rx).await;
+                        }
+                        Server::Multi(server) => server.call(AsgiApps { handlers }, rx).await,
+                    }
+
+                    // shutdown, in the reverse order of startup
+                    let mut result = Ok(());
+                    for lifespan in lifespans.into_iter().rev() {
+                        if let Err(e) = lifespan.shutdown().await {
+                            #[cfg(feature = "tracing")]
+                            tracing::error!("{e}");
+                            if result.is_ok() {
+                                result = Err(e);
+                            }
+                        }
                     }
 
-                    // receive the shutdown success, event though we don't care about it. without this the sender_rx gets dropped too early and the shutdown fails.
-                    lifespan_sender_rx.recv().await;
+                    stop_event_loops(event_loops).await;
+
+                    run_shutdown_hooks(shutdown_hooks).await;
 
                     if let Err(_e) = tx.send(()) {
                         #[cfg(feature = "tracing")]
                         tracing::error!("Failed to send shutdown completion");
                     }
 
-                    Ok(Python::with_gil(|py| py.None()))
+                    result.map(|_| Python::with_gil(|py| py.None()))
                 })
             }
             (_, _, _, _) => Err(PyErr::new::<PyRuntimeError, _>("Already started")),
@@ -230,11 +567,49 @@ impl ServerContext {
     }
 }
 
+/// Run the shutdown hooks in the reverse order they were added, carrying on past failures
+async fn run_shutdown_hooks(hooks: Vec<ShutdownHook>) {
+    for hook in hooks.into_iter().rev() {
+        if let Err(_e) = (hook.hook)().await {
+            #[cfg(feature = "tracing")]
+            tracing::error!("shutdown hook {} failed: {_e}", hook.name);
+        }
+    }
+}
+
 /// Create a server context wrapping the main server method
 pub fn create_server_context(
     app: PyObject,
     server: Box<dyn AsyncFn + Send + Sync>,
 ) -> Py<ServerContext> {
+    new_server_context(vec![("app".to_string(), app)], Server::Single(server))
+}
+
+/// Create a server context for several named ASGI apps, each with its own lifespan.
+///
+/// The apps are started in order, and stopped in the reverse order. If one of them fails to
+/// start, the apps that already started are stopped again. The server method gets an
+/// `AsgiHandler` for each app, so they can be mounted on different prefixes or hosts:
+///
+/// ```rust,ignore
+/// let ctx = parviocula::create_multi_server_context(
+///     vec![("users".to_string(), users_app), ("orders".to_string(), orders_app)],
+///     Box::new(|apps: AsgiApps, rx| async move {
+///         let app = Router::new()
+///             .nest_service("/users", apps["users"].clone().into_service())
+///             .fallback(apps["orders"].clone());
+///         // serve the router
+///     }),
+/// );
+/// ```
+pub fn create_multi_server_context(
+    apps: Vec<(String, PyObject)>,
+    server: Box<dyn MultiAsyncFn + Send + Sync>,
+) -> Py<ServerContext> {
+    new_server_context(apps, Server::Multi(server))
+}
+
+fn new_server_context(apps: Vec<(String, PyObject)>, server: Server) -> Py<ServerContext> {
     let (tx, rx) = tokio::sync::oneshot::channel();
     let (wait_shutdown_tx, wait_shutdown_rx) = tokio::sync::oneshot::channel();
     let ctx = ServerContext {
@@ -242,8 +617,16 @@ pub fn create_server_context(
         trigger_shutdown_rx: Some(rx),
         wait_shutdown_tx: Some(wait_shutdown_tx),
         wait_shutdown_rx: Some(wait_shutdown_rx),
-        app: Some(app),
+        apps: Some(apps),
         server: Some(server),
+        interface: AsgiInterface::Auto,
+        event_loops: 1,
+        app_factory: None,
+        routing_table: None,
+        coverage: None,
+        rust_app: None,
+        startup_hooks: Vec::new(),
+        shutdown_hooks: Vec::new(),
     };
     Python::with_gil(|py| Py::new(py, ctx).expect("failed to create context"))
 }
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use pyo3::{
+    exceptions::PyRuntimeError,
+    prelude::*,
+    types::{PyDict, PyString},
+};
+use tokio::sync::mpsc;
+
+use crate::{Receiver, Sender};
+
+/// A running ASGI lifespan of one application
+/// https://asgi.readthedocs.io/en/latest/specs/lifespan.html
+pub(crate) struct Lifespan {
+    receiver_tx: mpsc::UnboundedSender<Py<PyDict>>,
+    sender_rx: mpsc::UnboundedReceiver<Py<PyDict>>,
+}
+
+fn message(message_type: &str) -> PyResult<Py<PyDict>> {
+    Python::with_gil(|py| {
+        let scope = PyDict::new(py);
+        scope.set_item("type", message_type)?;
+        Ok(scope.into())
+    })
+}
+
+impl Lifespan {
+    /// Start the lifespan of `app` and wait for `lifespan.startup` to complete
+    pub(crate) async fn startup(
+        app: &PyObject,
+        locals: &Arc<pyo3_async_runtimes::TaskLocals>,
+        state: &Arc<Py<PyDict>>,
+    ) -> PyResult<Lifespan> {
+        let (receiver, receiver_tx) = Receiver::new();
+        let (sender, mut sender_rx) = Sender::new(locals.clone());
+
+        let lifespan = Python::with_gil(|py| {
+            let asgi = PyDict::new(py);
+            asgi.set_item("spec_version", "2.0")?;
+            asgi.set_item("version", "2.0")?;
+            let scope = PyDict::new(py);
+            scope.set_item("type", "lifespan")?;
+            scope.set_item("asgi", asgi)?;
+            scope.set_item("state", state.bind(py))?;
+
+            let sender = Py::new(py, sender)?;
+            let receiver = Py::new(py, receiver)?;
+            let args = (scope, receiver, sender);
+            let res = app.call_method1(py, "__call__", args)?;
+            let fut = res.extract(py)?;
+            pyo3_async_runtimes::into_future_with_locals(locals, fut)
+        })?;
+
+        if receiver_tx.send(message("lifespan.startup")?).is_err() {
+            return Err(PyErr::new::<PyRuntimeError, _>(
+                "Failed to send lifespan startup",
+            ));
+        }
+
+        // will continue running until the server sends lifespan.shutdown
+        tokio::spawn(async move {
+            if let Err(_e) = lifespan.await {
+                #[cfg(feature = "tracing")]
+                tracing::error!("Error processing lifespan: {_e}");
+            }
+        });
+
+        if let Some(resp) = sender_rx.recv().await {
+            Python::with_gil(|py| {
+                let dict: Bound<'_, PyDict> = resp.into_bound(py);
+                if let Ok(Some(value)) = dict.get_item("type") {
+                    let value: Bound<'_, PyString> = value.downcast_into()?;
+                    let value = value.to_str()?;
+                    if value == "lifespan.startup.complete" {
+                        return Ok(());
+                    }
+                }
+                Err(PyErr::new::<PyRuntimeError, _>(
+                    "Failed during asgi startup",
+                ))
+            })?;
+        }
+
+        Ok(Lifespan {
+            receiver_tx,
+            sender_rx,
+        })
+    }
+
+    /// Send `lifespan.shutdown` and wait for the application to respond
+    pub(crate) async fn shutdown(mut self) -> PyResult<()> {
+        if self
+            .receiver_tx
+            .send(message("lifespan.shutdown")?)
+            .is_err()
+        {
+            return Err(PyErr::new::<PyRuntimeError, _>(
+                "Failed to send lifespan shutdown",
+            ));
+        }
+
+        // receive the shutdown success, event though we don't care about it. without this the sender_rx gets dropped too early and the shutdown fails.
+        self.sender_rx.recv().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,465 @@
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use axum::{
+    body::{to_bytes, Body},
+    handler::Handler,
+    http::{Method, Request, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{Map, Value};
+use tokio::sync::OnceCell;
+
+use crate::{asgi::AsgiHandler, inventory::path_shape};
+
+const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024; // 16MB
+
+/// The operations of an OpenAPI path item, other keys (`parameters`, `summary`, ...) are shared
+const OPERATIONS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// Returned when the OpenAPI schema of the python application can't be fetched
+#[derive(Debug)]
+pub struct OpenApiError {
+    reason: String,
+}
+
+impl fmt::Display for OpenApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to fetch the python OpenAPI schema: {}",
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for OpenApiError {}
+
+#[derive(Clone)]
+struct OpenApiConfig {
+    path: String,
+    fragments: Vec<Value>,
+    rust_operations: Vec<(Method, String)>,
+    max_body_size: usize,
+}
+
+/// Serves an OpenAPI document combining the schema of the python application with the
+/// schemas of the rust handlers.
+///
+/// The python schema is fetched once, by calling the ASGI application's OpenAPI route
+/// (`/openapi.json` by default, as used by FastAPI) in process. Operations owned by rust, either
+/// listed with [`OpenApi::with_rust_operations`] or defined in one of the fragments, are removed
+/// from it, and the fragments (complete OpenAPI documents, e.g. generated with utoipa or aide)
+/// are merged in, with their `paths`, `components` and `tags` taking precedence. Paths are
+/// matched by their shape, so python's `/users/{id}` and rust's `/users/{user_id}` are the same
+/// path, which keeps the rust name.
+///
+/// Route it on the same path as the python schema, so clients keep finding it in the same place:
+///
+/// ```rust,ignore
+/// let openapi = OpenApi::new(asgi.clone()).with_fragment(serde_json::to_value(ApiDoc::openapi())?);
+/// Router::new()
+///     .route("/openapi.json", get(openapi))
+///     .fallback(asgi)
+/// ```
+#[derive(Clone)]
+pub struct OpenApi {
+    asgi: AsgiHandler,
+    config: Arc<OpenApiConfig>,
+    document: Arc<OnceCell<Value>>,
+}
+
+impl OpenApi {
+    pub fn new(asgi: AsgiHandler) -> OpenApi {
+        OpenApi {
+            asgi,
+            config: Arc::new(OpenApiConfig {
+                path: "/openapi.json".to_string(),
+                fragments: Vec::new(),
+                rust_operations: Vec::new(),
+                max_body_size: DEFAULT_MAX_BODY_SIZE,
+            }),
+            document: Arc::new(OnceCell::new()),
+        }
+    }
+
+    fn config_mut(&mut self) -> &mut OpenApiConfig {
+        // the document is built from the config, so changing it needs a new cache
+        self.document = Arc::new(OnceCell::new());
+        Arc::make_mut(&mut self.config)
+    }
+
+    /// Set the path of the python application's OpenAPI schema. Defaults to `/openapi.json`.
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.config_mut().path = path.into();
+        self
+    }
+
+    /// Add an OpenAPI document describing rust handlers
+    pub fn with_fragment(mut self, fragment: Value) -> Self {
+        self.config_mut().fragments.push(fragment);
+        self
+    }
+
+    /// Remove the given `(method, path)` operations from the python schema, using OpenAPI path
+    /// templates (e.g. `/users/{id}`, matching python's path whatever its parameters are named)
+    pub fn with_rust_operations<I, P>(mut self, operations: I) -> Self
+    where
+        I: IntoIterator<Item = (Method, P)>,
+        P: Into<String>,
+    {
+        self.config_mut().rust_operations.extend(
+            operations
+                .into_iter()
+                .map(|(method, path)| (method, path.into())),
+        );
+        self
+    }
+
+    /// The combined document, fetching the python schema on the first call
+    pub async fn document(&self) -> Result<Value, OpenApiError> {
+        self.document
+            .get_or_try_init(|| async {
+                let python = self.fetch().await?;
+                Ok(self.config.merge(python))
+            })
+            .await
+            .cloned()
+    }
+
+    async fn fetch(&self) -> Result<Value, OpenApiError> {
+        let error = |reason: String| OpenApiError { reason };
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri(&self.config.path)
+            .header("accept", "application/json")
+            .body(Body::empty())
+            .map_err(|e| error(e.to_string()))?;
+        let response = Handler::<AsgiHandler, ()>::call(self.asgi.clone(), req, ()).await;
+        if !response.status().is_success() {
+            return Err(error(format!("status {}", response.status())));
+        }
+        let body = to_bytes(response.into_body(), self.config.max_body_size)
+            .await
+            .map_err(|e| error(e.to_string()))?;
+        serde_json::from_slice(&body).map_err(|e| error(e.to_string()))
+    }
+}
+
+impl OpenApiConfig {
+    fn merge(&self, mut document: Value) -> Value {
+        let Some(root) = document.as_object_mut() else {
+            return document;
+        };
+
+        let mut owned = self.rust_operations.clone();
+        for fragment in &self.fragments {
+            for (path, item) in object(fragment, "paths") {
+                for method in OPERATIONS {
+                    if item.get(method).is_some() {
+                        if let Ok(method) = Method::from_bytes(method.to_uppercase().as_bytes()) {
+                            owned.push((method, path.clone()));
+                        }
+                    }
+                }
+            }
+        }
+
+        let paths = object_mut(root, "paths");
+        for (method, path) in &owned {
+            let method = method.as_str().to_lowercase();
+            let Some(path) = same_shape(paths, path) else {
+                continue;
+            };
+            if let Some(item) = paths.get_mut(&path).and_then(Value::as_object_mut) {
+                item.remove(&method);
+                if !OPERATIONS
+                    .iter()
+                    .any(|operation| item.contains_key(*operation))
+                {
+                    paths.remove(&path);
+                }
+            }
+        }
+
+        for fragment in &self.fragments {
+            let paths = object_mut(root, "paths");
+            for (path, item) in object(fragment, "paths") {
+                let Some(item) = item.as_object() else {
+                    continue;
+                };
+                // the python operations left on a path of the same shape move to the rust path,
+                // as two templates of the same shape aren't valid
+                if let Some(existing) = same_shape(paths, path).filter(|existing| existing != path)
+                {
+                    if let Some(mut moved) = paths.remove(&existing) {
+                        rename_path_parameters(&mut moved, &existing, path);
+                        paths.insert(path.clone(), moved);
+                    }
+                }
+                let merged = object_mut(paths, path);
+                for (key, value) in item {
+                    merged.insert(key.clone(), value.clone());
+                }
+            }
+
+            let components = object_mut(root, "components");
+            for (section, entries) in object(fragment, "components") {
+                let Some(entries) = entries.as_object() else {
+                    continue;
+                };
+                let merged = object_mut(components, section);
+                for (name, entry) in entries {
+                    merged.insert(name.clone(), entry.clone());
+                }
+            }
+
+            if let Some(tags) = fragment.get("tags").and_then(Value::as_array) {
+                let merged = root
+                    .entry("tags")
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                if let Some(merged) = merged.as_array_mut() {
+                    for tag in tags {
+                        let name = tag.get("name");
+                        match merged
+                            .iter_mut()
+                            .find(|existing| existing.get("name") == name)
+                        {
+                            Some(existing) => *existing = tag.clone(),
+                            None => merged.push(tag.clone()),
+                        }
+                    }
+                }
+            }
+        }
+        document
+    }
+}
+
+/// The path in `paths` with the same shape as `path`, preferring an exact match
+fn same_shape(paths: &Map<String, Value>, path: &str) -> Option<String> {
+    if paths.contains_key(path) {
+        return Some(path.to_string());
+    }
+    let shape = path_shape(path);
+    paths
+        .keys()
+        .find(|existing| path_shape(existing) == shape)
+        .cloned()
+}
+
+/// The parameter names of a path template, e.g. `["id"]` for `/users/{id}`
+fn parameter_names(path: &str) -> Vec<&str> {
+    path.split('{')
+        .skip(1)
+        .filter_map(|part| part.split_once('}'))
+        .map(|(name, _)| name.trim_start_matches('*'))
+        .collect()
+}
+
+/// Rename the path parameters of a path item moved from the `from` template to `to`, in its
+/// shared and per operation `parameters`
+fn rename_path_parameters(item: &mut Value, from: &str, to: &str) {
+    let renames = parameter_names(from)
+        .into_iter()
+        .zip(parameter_names(to))
+        .filter(|(from, to)| from != to)
+        .collect::<Vec<_>>();
+    if renames.is_empty() {
+        return;
+    }
+    let Some(item) = item.as_object_mut() else {
+        return;
+    };
+    let parameters = item
+        .iter_mut()
+        .filter_map(|(key, value)| match key.as_str() {
+            "parameters" => Some(value),
+            key if OPERATIONS.contains(&key) => value.get_mut("parameters"),
+            _ => None,
+        });
+    for parameters in parameters {
+        for parameter in parameters.as_array_mut().into_iter().flatten() {
+            if parameter.get("in").and_then(Value::as_str) != Some("path") {
+                continue;
+            }
+            let name = parameter.get("name").and_then(Value::as_str);
+            if let Some((_, to)) = renames.iter().find(|(from, _)| Some(*from) == name) {
+                parameter["name"] = Value::String(to.to_string());
+            }
+        }
+    }
+}
+
+fn object<'a>(value: &'a Value, key: &str) -> impl Iterator<Item = (&'a String, &'a Value)> {
+    value
+        .get(key)
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+}
+
+/// Get the object at `key`, replacing anything that isn't an object
+fn object_mut<'a>(map: &'a mut Map<String, Value>, key: &str) -> &'a mut Map<String, Value> {
+    let value = map.entry(key).or_insert_with(|| Value::Object(Map::new()));
+    if !value.is_object() {
+        *value = Value::Object(Map::new());
+    }
+    match value {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    }
+}
+
+impl<S> Handler<OpenApi, S> for OpenApi {
+    type Future = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+    fn call(self, _req: Request<Body>, _state: S) -> Self::Future {
+        Box::pin(async move {
+            match self.document().await {
+                Ok(document) => Json(document).into_response(),
+                Err(_e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::error!("{_e}");
+                    StatusCode::BAD_GATEWAY.into_response()
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn config(fragments: Vec<Value>, rust_operations: Vec<(Method, &str)>) -> OpenApiConfig {
+        OpenApiConfig {
+            path: "/openapi.json".to_string(),
+            fragments,
+            rust_operations: rust_operations
+                .into_iter()
+                .map(|(method, path)| (method, path.to_string()))
+                .collect(),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    fn python() -> Value {
+        json!({
+            "openapi": "3.1.0",
+            "paths": {
+                "/users": {
+                    "get": {"summary": "python list"},
+                    "post": {"summary": "python create"},
+                },
+                "/users/{id}": {
+                    "parameters": [{"name": "id", "in": "path"}],
+                    "get": {"summary": "python get"},
+                },
+                "/health": {"get": {"summary": "python health"}},
+            },
+            "components": {"schemas": {"User": {"title": "python"}, "Error": {}}},
+            "tags": [{"name": "users", "description": "python"}],
+        })
+    }
+
+    #[test]
+    fn removes_rust_operations() {
+        let document = config(
+            Vec::new(),
+            vec![(Method::POST, "/users"), (Method::GET, "/users/{id}")],
+        )
+        .merge(python());
+        let paths = &document["paths"];
+        assert_eq!(paths["/users"], json!({"get": {"summary": "python list"}}));
+        // paths without operations left are removed, shared keys and all
+        assert!(paths.get("/users/{id}").is_none());
+        assert_eq!(paths["/health"], python()["paths"]["/health"]);
+    }
+
+    #[test]
+    fn merges_fragments() {
+        let fragment = json!({
+            "paths": {
+                "/users": {"get": {"summary": "rust list"}},
+                "/orders": {"get": {"summary": "rust orders"}},
+            },
+            "components": {"schemas": {"User": {"title": "rust"}}},
+            "tags": [
+                {"name": "users", "description": "rust"},
+                {"name": "orders"},
+            ],
+        });
+        let document = config(vec![fragment], Vec::new()).merge(python());
+        assert_eq!(
+            document["paths"]["/users"],
+            json!({
+                "get": {"summary": "rust list"},
+                "post": {"summary": "python create"},
+            })
+        );
+        assert_eq!(
+            document["paths"]["/orders"],
+            json!({"get": {"summary": "rust orders"}})
+        );
+        assert_eq!(
+            document["components"]["schemas"],
+            json!({"User": {"title": "rust"}, "Error": {}})
+        );
+        assert_eq!(
+            document["tags"],
+            json!([{"name": "users", "description": "rust"}, {"name": "orders"}])
+        );
+        assert_eq!(document["openapi"], "3.1.0");
+    }
+
+    #[test]
+    fn fragment_operations_replace_python_paths() {
+        // the python path loses its shared keys along with its last operation
+        let fragment = json!({"paths": {"/users/{id}": {"get": {"summary": "rust get"}}}});
+        let document = config(vec![fragment], Vec::new()).merge(python());
+        assert_eq!(
+            document["paths"]["/users/{id}"],
+            json!({"get": {"summary": "rust get"}})
+        );
+    }
+
+    #[test]
+    fn matches_paths_by_shape() {
+        let document = config(Vec::new(), vec![(Method::GET, "/users/{user_id}")]).merge(python());
+        assert!(document["paths"].get("/users/{id}").is_none());
+
+        let mut python = python();
+        python["paths"]["/users/{id}"]["delete"] = json!({
+            "summary": "python delete",
+            "parameters": [{"name": "id", "in": "path"}, {"name": "id", "in": "query"}],
+        });
+        let fragment = json!({"paths": {"/users/{user_id}": {"get": {"summary": "rust get"}}}});
+        let document = config(vec![fragment], Vec::new()).merge(python);
+        assert!(document["paths"].get("/users/{id}").is_none());
+        assert_eq!(
+            document["paths"]["/users/{user_id}"],
+            json!({
+                "parameters": [{"name": "user_id", "in": "path"}],
+                "get": {"summary": "rust get"},
+                "delete": {
+                    "summary": "python delete",
+                    "parameters": [
+                        {"name": "user_id", "in": "path"},
+                        {"name": "id", "in": "query"},
+                    ],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn leaves_invalid_documents_alone() {
+        let document = config(Vec::new(), vec![(Method::GET, "/users")]).merge(json!([]));
+        assert_eq!(document, json!([]));
+    }
+}
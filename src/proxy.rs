@@ -0,0 +1,287 @@
+use std::{fmt, future::Future, pin::Pin, str::FromStr};
+
+use axum::{
+    body::Body,
+    handler::Handler,
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        response,
+        uri::{Authority, PathAndQuery, Scheme},
+        Request, StatusCode, Uri, Version,
+    },
+    response::{IntoResponse, Response},
+};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    asgi::{json_response, AsgiCallError, Backend},
+    forwarded::{ForwardedInfo, ProxyHeaders},
+};
+
+/// Returned when parsing an invalid upstream address
+#[derive(Debug)]
+pub struct InvalidUpstream(String);
+
+impl fmt::Display for InvalidUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid upstream {}, expected `http://host:port` or `unix:/path`",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidUpstream {}
+
+#[derive(Clone)]
+enum Upstream {
+    Http(Client<HttpConnector, Body>),
+    #[cfg(unix)]
+    Unix(Client<unix::UnixConnector, Body>),
+}
+
+/// An axum handler forwarding requests to a python application running in its own server (e.g.
+/// uvicorn), usable anywhere an [`AsgiHandler`](crate::AsgiHandler) is, so moving the python
+/// application in or out of process is a configuration change.
+///
+/// The upstream is either `http://host:port`, optionally with a path prefix, or a unix socket
+/// as `unix:/path/to/socket` (like uvicorn's `--uds`). Request and response bodies are streamed,
+/// hop-by-hop headers are removed, the `Host` header is kept and `X-Forwarded-For` and
+/// `X-Forwarded-Proto` are set from the resolved [`ForwardedInfo`], so uvicorn should run with
+/// `--proxy-headers` trusting this server. Upgrades (e.g. websockets) aren't proxied.
+///
+/// Responses are marked as served by python with [`Backend::Asgi`], and upstream connection
+/// errors become a 502.
+///
+/// ```rust,ignore
+/// let python = ProxyHandler::new("unix:/run/myproject/uvicorn.sock")?;
+/// Router::new().route("/users", get(get_users)).fallback(python)
+/// ```
+#[derive(Clone)]
+pub struct ProxyHandler {
+    upstream: Upstream,
+    scheme: Scheme,
+    authority: Authority,
+    prefix: String,
+    proxy_headers: Option<ProxyHeaders>,
+}
+
+impl FromStr for ProxyHandler {
+    type Err = InvalidUpstream;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ProxyHandler::new(s)
+    }
+}
+
+impl ProxyHandler {
+    pub fn new(upstream: &str) -> Result<ProxyHandler, InvalidUpstream> {
+        let invalid = || InvalidUpstream(upstream.to_string());
+        let builder = Client::builder(TokioExecutor::new());
+        #[cfg(unix)]
+        if let Some(path) = upstream.strip_prefix("unix:") {
+            return Ok(ProxyHandler {
+                upstream: Upstream::Unix(builder.build(unix::UnixConnector::new(path))),
+                scheme: Scheme::HTTP,
+                authority: Authority::from_static("localhost"),
+                prefix: String::new(),
+                proxy_headers: None,
+            });
+        }
+        let uri = upstream.parse::<Uri>().map_err(|_| invalid())?;
+        if uri.scheme() != Some(&Scheme::HTTP) || uri.query().is_some() {
+            return Err(invalid());
+        }
+        Ok(ProxyHandler {
+            upstream: Upstream::Http(builder.build_http()),
+            scheme: Scheme::HTTP,
+            authority: uri.authority().ok_or_else(invalid)?.clone(),
+            prefix: uri.path().trim_end_matches('/').to_string(),
+            proxy_headers: None,
+        })
+    }
+
+    /// Resolve the `X-Forwarded-For` and `X-Forwarded-Proto` headers sent upstream from the
+    /// proxy headers of requests coming from trusted proxies, see
+    /// [`AsgiHandler::with_proxy_headers`](crate::AsgiHandler::with_proxy_headers).
+    pub fn with_proxy_headers(mut self, proxy_headers: ProxyHeaders) -> ProxyHandler {
+        self.proxy_headers = Some(proxy_headers);
+        self
+    }
+
+    /// Send a request upstream, see [`AsgiHandler::send`](crate::AsgiHandler::send)
+    pub async fn send(self, req: Request<Body>) -> Response {
+        Handler::<ProxyHandler, ()>::call(self, req, ()).await
+    }
+
+    /// Send a request upstream and deserialize the JSON response body, see
+    /// [`AsgiHandler::send_json`](crate::AsgiHandler::send_json)
+    pub async fn send_json<T: DeserializeOwned>(
+        self,
+        req: Request<Body>,
+    ) -> Result<(response::Parts, T), AsgiCallError> {
+        json_response(self.send(req).await).await
+    }
+
+    fn upstream_request(&self, req: Request<Body>) -> Result<Request<Body>, StatusCode> {
+        let (mut parts, body) = req.into_parts();
+        let proxy_headers = self
+            .proxy_headers
+            .as_ref()
+            .or_else(|| parts.extensions.get::<ProxyHeaders>());
+        let forwarded = ForwardedInfo::from_parts(&parts, proxy_headers);
+
+        let path_and_query = parts.uri.path_and_query().map_or("/", PathAndQuery::as_str);
+        let uri = Uri::builder()
+            .scheme(self.scheme.clone())
+            .authority(self.authority.clone())
+            .path_and_query(format!("{}{path_and_query}", self.prefix))
+            .build()
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+        let headers = &mut parts.headers;
+        remove_hop_by_hop(headers);
+        // http/2 requests have their host in the uri instead
+        if !headers.contains_key(header::HOST) {
+            if let Some(host) = parts
+                .uri
+                .authority()
+                .and_then(|authority| HeaderValue::from_str(authority.as_str()).ok())
+            {
+                headers.insert(header::HOST, host);
+            }
+        }
+        // untrusted values must not reach the upstream, trusted ones are resolved already
+        headers.remove(header::FORWARDED);
+        headers.remove("x-forwarded-host");
+        headers.remove("x-forwarded-port");
+        let x_forwarded_for = HeaderName::from_static("x-forwarded-for");
+        match forwarded
+            .client
+            .and_then(|(host, _)| HeaderValue::from_str(&host).ok())
+        {
+            Some(client) => headers.insert(x_forwarded_for, client),
+            None => headers.remove(x_forwarded_for),
+        };
+        if let Ok(scheme) = HeaderValue::from_str(&forwarded.scheme) {
+            headers.insert("x-forwarded-proto", scheme);
+        }
+
+        let mut upstream = Request::new(body);
+        *upstream.method_mut() = parts.method;
+        *upstream.uri_mut() = uri;
+        *upstream.version_mut() = Version::HTTP_11;
+        *upstream.headers_mut() = parts.headers;
+        Ok(upstream)
+    }
+}
+
+/// https://www.rfc-editor.org/rfc/rfc9110#section-7.6.1
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    let listed = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_str(name.trim()).ok())
+        .collect::<Vec<_>>();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in [
+        header::CONNECTION,
+        header::PROXY_AUTHENTICATE,
+        header::PROXY_AUTHORIZATION,
+        header::TE,
+        header::TRAILER,
+        header::TRANSFER_ENCODING,
+        header::UPGRADE,
+    ] {
+        headers.remove(name);
+    }
+    headers.remove("keep-alive");
+    headers.remove("proxy-connection");
+}
+
+impl<S> Handler<ProxyHandler, S> for ProxyHandler {
+    type Future = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+    fn call(self, req: Request<Body>, _state: S) -> Self::Future {
+        Box::pin(async move {
+            let mut response = match self.upstream_request(req) {
+                Ok(req) => {
+                    let response = match &self.upstream {
+                        Upstream::Http(client) => client.request(req).await,
+                        #[cfg(unix)]
+                        Upstream::Unix(client) => client.request(req).await,
+                    };
+                    match response {
+                        Ok(response) => {
+                            let (mut parts, body) = response.into_parts();
+                            remove_hop_by_hop(&mut parts.headers);
+                            Response::from_parts(parts, Body::new(body))
+                        }
+                        Err(_e) => {
+                            #[cfg(feature = "tracing")]
+                            tracing::error!("error proxying request: {_e}");
+                            StatusCode::BAD_GATEWAY.into_response()
+                        }
+                    }
+                }
+                Err(status) => status.into_response(),
+            };
+            response.extensions_mut().insert(Backend::Asgi);
+            response
+        })
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        future::Future,
+        io,
+        path::PathBuf,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    };
+
+    use axum::http::Uri;
+    use hyper_util::rt::TokioIo;
+    use tokio::net::UnixStream;
+
+    /// Connects to the same unix socket, whatever the uri
+    #[derive(Clone)]
+    pub(super) struct UnixConnector {
+        path: Arc<PathBuf>,
+    }
+
+    impl UnixConnector {
+        pub(super) fn new(path: &str) -> UnixConnector {
+            UnixConnector {
+                path: Arc::new(PathBuf::from(path)),
+            }
+        }
+    }
+
+    impl tower::Service<Uri> for UnixConnector {
+        type Response = TokioIo<UnixStream>;
+        type Error = io::Error;
+        type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _uri: Uri) -> Self::Future {
+            let path = self.path.clone();
+            Box::pin(async move { UnixStream::connect(&*path).await.map(TokioIo::new) })
+        }
+    }
+}
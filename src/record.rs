@@ -0,0 +1,540 @@
+use std::{
+    fmt,
+    fs::OpenOptions,
+    future::Future,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    pin::Pin,
+    sync::{mpsc, Arc},
+};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    handler::Handler,
+    http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, Uri, Version},
+    response::Response,
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Map, Value};
+use tower::ServiceExt;
+
+use crate::{
+    asgi::AsgiHandler,
+    request::{bodies_equal, buffer_body, copy_request, DEFAULT_MAX_BODY_SIZE},
+};
+
+const REDACTED: &str = "[redacted]";
+
+/// The number of lines waiting for the writer thread before new ones are dropped
+const WRITER_CAPACITY: usize = 1024;
+
+#[derive(Clone)]
+struct RecorderConfig {
+    sample_rate: f64,
+    redacted_headers: Vec<HeaderName>,
+    max_body_size: usize,
+}
+
+/// Records requests handled by the [`AsgiHandler`] and the responses of the ASGI application,
+/// one JSON object per line, so they can be replayed against the rust router with [`Replay`].
+///
+/// The values of sensitive headers (`authorization`, `proxy-authorization`, `cookie`,
+/// `set-cookie` and `x-api-key` by default) are replaced with `[redacted]`. Bodies are stored
+/// as text when they're valid UTF-8, and base64 encoded otherwise. Lines are written by a
+/// background thread, so recording doesn't block the runtime on file IO, and are dropped when
+/// the thread falls behind.
+///
+/// ```rust,ignore
+/// let asgi = AsgiHandler::new_with_locals(app, locals)
+///     .with_recorder(Recorder::new("fixtures.jsonl")?.with_sample_rate(0.01));
+/// ```
+#[derive(Clone)]
+pub struct Recorder {
+    tx: mpsc::SyncSender<String>,
+    config: Arc<RecorderConfig>,
+}
+
+impl Recorder {
+    /// Append the recorded exchanges to the file at `path`, creating it if needed
+    pub fn new(path: impl AsRef<Path>) -> std::io::Result<Recorder> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (tx, rx) = mpsc::sync_channel::<String>(WRITER_CAPACITY);
+        std::thread::spawn(move || {
+            for line in rx {
+                if let Err(_e) = writeln!(file, "{line}") {
+                    #[cfg(feature = "tracing")]
+                    tracing::error!("failed to write recorded request: {_e}");
+                }
+            }
+        });
+        Ok(Recorder {
+            tx,
+            config: Arc::new(RecorderConfig {
+                sample_rate: 1.0,
+                redacted_headers: vec![
+                    HeaderName::from_static("authorization"),
+                    HeaderName::from_static("proxy-authorization"),
+                    HeaderName::from_static("cookie"),
+                    HeaderName::from_static("set-cookie"),
+                    HeaderName::from_static("x-api-key"),
+                ],
+                max_body_size: DEFAULT_MAX_BODY_SIZE,
+            }),
+        })
+    }
+
+    /// Record only a share (0 to 1) of the requests. Defaults to recording every request.
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        Arc::make_mut(&mut self.config).sample_rate = sample_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the headers whose values are redacted, in both requests and responses
+    pub fn with_redacted_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        Arc::make_mut(&mut self.config).redacted_headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Set the maximum size of the request and response bodies. Exchanges with a larger body
+    /// aren't recorded and are passed through untouched. Defaults to 4MB.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        Arc::make_mut(&mut self.config).max_body_size = max_body_size;
+        self
+    }
+
+    pub(crate) fn sample(&self) -> bool {
+        let rate = self.config.sample_rate;
+        rate >= 1.0 || fastrand::f64() < rate
+    }
+
+    /// Call the ASGI application and record the request and the response
+    pub(crate) fn record(
+        self,
+        asgi: AsgiHandler,
+        req: Request<Body>,
+    ) -> Pin<Box<dyn Future<Output = Response<Body>> + Send>> {
+        Box::pin(async move {
+            let config = &self.config;
+            let (parts, body) = req.into_parts();
+            let body = match buffer_body(body, config.max_body_size).await {
+                Ok(body) => body,
+                Err(body) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("request body too large to record, skipping it");
+                    let req = Request::from_parts(parts, body);
+                    return Handler::<AsgiHandler, ()>::call(asgi, req, ()).await;
+                }
+            };
+            let request = json!({
+                "method": parts.method.as_str(),
+                "uri": parts.uri.to_string(),
+                "http_version": http_version(parts.version),
+                "headers": self.headers(&parts.headers),
+                "body": body_to_json(&body),
+            });
+
+            let response =
+                Handler::<AsgiHandler, ()>::call(asgi, copy_request(&parts, &body), ()).await;
+            let (parts, body) = response.into_parts();
+            let body = match buffer_body(body, config.max_body_size).await {
+                Ok(body) => body,
+                Err(body) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("response body too large to record, skipping it");
+                    return Response::from_parts(parts, body);
+                }
+            };
+            let exchange = json!({
+                "request": request,
+                "response": {
+                    "status": parts.status.as_u16(),
+                    "headers": self.headers(&parts.headers),
+                    "body": body_to_json(&body),
+                },
+            });
+            if let Err(mpsc::TrySendError::Full(_)) = self.tx.try_send(exchange.to_string()) {
+                #[cfg(feature = "tracing")]
+                tracing::warn!("fixture writer is falling behind, dropping recorded request");
+            }
+            Response::from_parts(parts, Body::from(body))
+        })
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> Value {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if self.config.redacted_headers.contains(name) {
+                    REDACTED.to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                json!([name.as_str(), value])
+            })
+            .collect()
+    }
+}
+
+/// The version as written in fixtures, the same as the ASGI scope's `http_version`
+fn http_version(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "1.1",
+    }
+}
+
+fn body_to_json(body: &Bytes) -> Value {
+    match std::str::from_utf8(body) {
+        Ok(text) => json!({ "text": text }),
+        Err(_) => json!({ "base64": STANDARD.encode(body) }),
+    }
+}
+
+fn body_from_json(body: Option<&Value>) -> Result<Bytes, String> {
+    let Some(body) = body.and_then(Value::as_object) else {
+        return Ok(Bytes::new());
+    };
+    if let Some(text) = body.get("text").and_then(Value::as_str) {
+        Ok(Bytes::from(text.to_string()))
+    } else if let Some(encoded) = body.get("base64").and_then(Value::as_str) {
+        STANDARD
+            .decode(encoded)
+            .map(Bytes::from)
+            .map_err(|e| format!("invalid base64 body: {e}"))
+    } else {
+        Ok(Bytes::new())
+    }
+}
+
+fn headers_from_json(headers: Option<&Value>) -> Result<HeaderMap, String> {
+    let mut map = HeaderMap::new();
+    for header in headers.and_then(Value::as_array).into_iter().flatten() {
+        let (Some(name), Some(value)) = (
+            header.get(0).and_then(Value::as_str),
+            header.get(1).and_then(Value::as_str),
+        ) else {
+            return Err("headers must be `[name, value]` pairs".to_string());
+        };
+        map.append(
+            HeaderName::from_bytes(name.as_bytes()).map_err(|e| e.to_string())?,
+            HeaderValue::from_str(value).map_err(|e| e.to_string())?,
+        );
+    }
+    Ok(map)
+}
+
+/// A request and response recorded by a [`Recorder`].
+#[derive(Clone, Debug)]
+pub struct Fixture {
+    pub method: Method,
+    pub uri: Uri,
+    pub version: Version,
+    pub request_headers: HeaderMap,
+    pub request_body: Bytes,
+    pub status: StatusCode,
+    pub response_headers: HeaderMap,
+    pub response_body: Bytes,
+}
+
+/// Returned when a file of recorded requests can't be loaded
+#[derive(Debug)]
+pub struct InvalidFixture {
+    line: usize,
+    reason: String,
+}
+
+impl fmt::Display for InvalidFixture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid fixture on line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidFixture {}
+
+impl Fixture {
+    /// Load all the fixtures from a file written by a [`Recorder`]
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Vec<Fixture>> {
+        let file = std::fs::File::open(path)?;
+        let mut fixtures = Vec::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fixture = Fixture::parse(&line).map_err(|reason| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    InvalidFixture {
+                        line: index + 1,
+                        reason,
+                    },
+                )
+            })?;
+            fixtures.push(fixture);
+        }
+        Ok(fixtures)
+    }
+
+    fn parse(line: &str) -> Result<Fixture, String> {
+        let exchange: Map<String, Value> = serde_json::from_str(line).map_err(|e| e.to_string())?;
+        let empty = Map::new();
+        let field = |name: &str| {
+            exchange
+                .get(name)
+                .and_then(Value::as_object)
+                .unwrap_or(&empty)
+        };
+        let (request, response) = (field("request"), field("response"));
+        let method = request
+            .get("method")
+            .and_then(Value::as_str)
+            .ok_or("missing request method")?;
+        let uri = request
+            .get("uri")
+            .and_then(Value::as_str)
+            .ok_or("missing request uri")?;
+        let version = match request.get("http_version").and_then(Value::as_str) {
+            Some("0.9") => Version::HTTP_09,
+            Some("1.0") => Version::HTTP_10,
+            Some("1.1") | None => Version::HTTP_11,
+            Some("2") => Version::HTTP_2,
+            Some("3") => Version::HTTP_3,
+            Some(version) => return Err(format!("invalid http version: {version}")),
+        };
+        let status = response
+            .get("status")
+            .and_then(Value::as_u64)
+            .ok_or("missing response status")?;
+        Ok(Fixture {
+            method: Method::from_bytes(method.as_bytes()).map_err(|e| e.to_string())?,
+            uri: uri
+                .parse()
+                .map_err(|e: axum::http::uri::InvalidUri| e.to_string())?,
+            version,
+            request_headers: headers_from_json(request.get("headers"))?,
+            request_body: body_from_json(request.get("body"))?,
+            status: u16::try_from(status)
+                .ok()
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .ok_or("invalid response status")?,
+            response_headers: headers_from_json(response.get("headers"))?,
+            response_body: body_from_json(response.get("body"))?,
+        })
+    }
+
+    /// The recorded request
+    pub fn request(&self) -> Request<Body> {
+        let mut req = Request::new(Body::from(self.request_body.clone()));
+        *req.method_mut() = self.method.clone();
+        *req.uri_mut() = self.uri.clone();
+        *req.version_mut() = self.version;
+        *req.headers_mut() = self.request_headers.clone();
+        req
+    }
+}
+
+/// A difference between a recorded response and the response of the rust router.
+#[derive(Debug)]
+pub struct ReplayMismatch {
+    pub method: Method,
+    pub uri: Uri,
+    /// The recorded and replayed status, if they differ
+    pub status: Option<(StatusCode, StatusCode)>,
+    /// The compared headers that differ, with the recorded and replayed values
+    pub headers: Vec<(HeaderName, Vec<HeaderValue>, Vec<HeaderValue>)>,
+    /// The recorded and replayed bodies, if they differ
+    pub body: Option<(Bytes, Bytes)>,
+    /// The error reading the replayed body, if it failed
+    pub body_error: Option<String>,
+}
+
+impl fmt::Display for ReplayMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.uri)?;
+        if let Some((recorded, replayed)) = &self.status {
+            write!(f, ", status {recorded} != {replayed}")?;
+        }
+        for (name, recorded, replayed) in &self.headers {
+            write!(f, ", header {name} {recorded:?} != {replayed:?}")?;
+        }
+        if let Some((recorded, replayed)) = &self.body {
+            write!(
+                f,
+                ", body differs ({} bytes != {} bytes)",
+                recorded.len(),
+                replayed.len()
+            )?;
+        }
+        if let Some(error) = &self.body_error {
+            write!(f, ", failed to read body: {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Replays recorded requests against a rust router and compares the responses with the
+/// recorded responses of the ASGI application, to turn real python behaviour into regression
+/// tests for the rust handlers.
+///
+/// The status and body are always compared, headers only when listed with
+/// [`Replay::with_compared_headers`]. Redacted request headers are replayed as `[redacted]`
+/// unless replaced with [`Replay::with_request_header`].
+///
+/// ```rust,ignore
+/// #[tokio::test]
+/// async fn matches_python() {
+///     let mismatches = Replay::new(Fixture::load("fixtures.jsonl").unwrap())
+///         .with_json_body_comparison(true)
+///         .run(app())
+///         .await;
+///     assert!(mismatches.is_empty(), "{mismatches:#?}");
+/// }
+/// ```
+pub struct Replay {
+    fixtures: Vec<Fixture>,
+    compared_headers: Vec<HeaderName>,
+    request_headers: HeaderMap,
+    json_body: bool,
+}
+
+impl Replay {
+    pub fn new(fixtures: Vec<Fixture>) -> Replay {
+        Replay {
+            fixtures,
+            compared_headers: Vec::new(),
+            request_headers: HeaderMap::new(),
+            json_body: false,
+        }
+    }
+
+    /// Compare the values of the given response headers
+    pub fn with_compared_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.compared_headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Replace a request header in every replayed request, e.g. a redacted `authorization`
+    pub fn with_request_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.request_headers.insert(name, value);
+        self
+    }
+
+    /// Compare the recorded and replayed bodies as JSON values when both are valid JSON, so
+    /// differences in formatting and key order between python and rust serializers are ignored
+    pub fn with_json_body_comparison(mut self, json_body: bool) -> Self {
+        self.json_body = json_body;
+        self
+    }
+
+    /// Send every fixture's request to the router in order, returning the mismatched responses
+    pub async fn run(&self, router: Router) -> Vec<ReplayMismatch> {
+        let mut mismatches = Vec::new();
+        for fixture in &self.fixtures {
+            let mut req = fixture.request();
+            for (name, value) in &self.request_headers {
+                req.headers_mut().insert(name, value.clone());
+            }
+            let response = match router.clone().oneshot(req).await {
+                Ok(response) => response,
+                Err(infallible) => match infallible {},
+            };
+            let (parts, body) = response.into_parts();
+            let body = to_bytes(body, usize::MAX).await;
+            if let Some(mismatch) = self.compare(fixture, parts.status, &parts.headers, body) {
+                mismatches.push(mismatch);
+            }
+        }
+        mismatches
+    }
+
+    fn compare(
+        &self,
+        fixture: &Fixture,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: Result<Bytes, axum::Error>,
+    ) -> Option<ReplayMismatch> {
+        let headers = self
+            .compared_headers
+            .iter()
+            .filter_map(|name| {
+                let recorded = fixture
+                    .response_headers
+                    .get_all(name)
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let replayed = headers.get_all(name).iter().cloned().collect::<Vec<_>>();
+                (recorded != replayed).then(|| (name.clone(), recorded, replayed))
+            })
+            .collect::<Vec<_>>();
+        // a body that can't be read never matches, rather than being compared as empty
+        let (body, body_error) = match body {
+            Ok(body) => {
+                let body_equal = bodies_equal(&fixture.response_body, &body, self.json_body);
+                (
+                    (!body_equal).then(|| (fixture.response_body.clone(), body)),
+                    None,
+                )
+            }
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let mismatch = ReplayMismatch {
+            method: fixture.method.clone(),
+            uri: fixture.uri.clone(),
+            status: (fixture.status != status).then_some((fixture.status, status)),
+            headers,
+            body,
+            body_error,
+        };
+        (mismatch.status.is_some()
+            || !mismatch.headers.is_empty()
+            || mismatch.body.is_some()
+            || mismatch.body_error.is_some())
+        .then_some(mismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fixtures() {
+        let fixture = Fixture::parse(
+            r#"{"request": {"method": "POST", "uri": "/users?x=1", "http_version": "2", "headers": [["content-type", "application/json"]], "body": {"text": "{}"}}, "response": {"status": 201, "headers": [], "body": {"base64": "AAE="}}}"#,
+        )
+        .unwrap();
+        assert_eq!(fixture.method, Method::POST);
+        assert_eq!(fixture.uri, "/users?x=1");
+        assert_eq!(fixture.version, Version::HTTP_2);
+        assert_eq!(fixture.request_headers["content-type"], "application/json");
+        assert_eq!(fixture.request_body, "{}");
+        assert_eq!(fixture.status, StatusCode::CREATED);
+        assert_eq!(fixture.response_body, &[0, 1][..]);
+    }
+
+    #[test]
+    fn round_trips_http_versions() {
+        for version in [Version::HTTP_10, Version::HTTP_11, Version::HTTP_2] {
+            let line = json!({
+                "request": {"method": "GET", "uri": "/", "http_version": http_version(version)},
+                "response": {"status": 200},
+            });
+            let fixture = Fixture::parse(&line.to_string()).unwrap();
+            assert_eq!(fixture.version, version);
+        }
+        let line = r#"{"request": {"method": "GET", "uri": "/", "http_version": "HTTP/1.1"}, "response": {"status": 200}}"#;
+        assert!(Fixture::parse(line).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::OriginalUri,
+    http::{request::Parts, Request, StatusCode},
+};
+use futures::{stream, StreamExt};
+use http_body_util::{BodyExt, LengthLimitError};
+use hyper::body::Frame;
+
+/// The default limit for bodies buffered in memory, unless configured otherwise
+pub(crate) const DEFAULT_MAX_BODY_SIZE: usize = 4 * 1024 * 1024; // 4MB
+
+/// Create a copy of a request whose body has already been buffered, so the same request can be
+/// sent to more than one handler.
+pub(crate) fn copy_request(parts: &Parts, body: &Bytes) -> Request<Body> {
+    let mut req = Request::new(Body::from(body.clone()));
+    *req.method_mut() = parts.method.clone();
+    *req.uri_mut() = parts.uri.clone();
+    *req.version_mut() = parts.version;
+    *req.headers_mut() = parts.headers.clone();
+    *req.extensions_mut() = parts.extensions.clone();
+    req
+}
+
+/// The prefix stripped from the path by `Router::nest_service` (or `nest`), and the full path,
+/// both still percent encoded. These are the `root_path` and `path` of an ASGI scope, as with
+/// uvicorn's `--root-path`. The prefix is empty outside nested routers.
+pub(crate) fn mount_path(parts: &Parts) -> (&str, &str) {
+    let path = parts.uri.path();
+    let Some(OriginalUri(original)) = parts.extensions.get::<OriginalUri>() else {
+        return ("", path);
+    };
+    let full = original.path();
+    match full.strip_suffix(path) {
+        Some(prefix) => (prefix, full),
+        // requests for the prefix itself get `/` as their path
+        None if path == "/" => (full, full),
+        None => ("", path),
+    }
+}
+
+/// The status for a request body that failed to be buffered with `to_bytes`: `413 Payload Too
+/// Large` if it was over the limit, `400 Bad Request` if reading it failed
+pub(crate) fn body_error_status(error: &axum::Error) -> StatusCode {
+    match std::error::Error::source(error) {
+        Some(source) if source.is::<LengthLimitError>() => StatusCode::PAYLOAD_TOO_LARGE,
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
+
+/// Whether two bodies are equal. With `json`, bodies that are both valid JSON are compared as
+/// JSON values, ignoring formatting and key order.
+pub(crate) fn bodies_equal(a: &[u8], b: &[u8], json: bool) -> bool {
+    if json {
+        if let (Ok(a), Ok(b)) = (
+            serde_json::from_slice::<serde_json::Value>(a),
+            serde_json::from_slice::<serde_json::Value>(b),
+        ) {
+            return a == b;
+        }
+    }
+    a == b
+}
+
+/// Buffer a body of at most `limit` bytes. Larger bodies, or bodies failing to be read, are
+/// given back as a body streaming the same data, starting with the bytes already read, so the
+/// request or response can still be passed on untouched.
+pub(crate) async fn buffer_body(body: Body, limit: usize) -> Result<Bytes, Body> {
+    if body.size_hint().lower() > limit as u64 {
+        return Err(body);
+    }
+    let mut body = body;
+    let mut buffered = Vec::new();
+    while let Some(frame) = body.frame().await {
+        let data = match frame.map(Frame::into_data) {
+            Ok(Ok(data)) => data,
+            // trailers aren't kept
+            Ok(Err(_)) => continue,
+            Err(e) => {
+                let read = [Ok(Bytes::from(buffered)), Err(e)];
+                return Err(Body::from_stream(stream::iter(read)));
+            }
+        };
+        if buffered.len() + data.len() > limit {
+            let read = [Ok(Bytes::from(buffered)), Ok(data)];
+            let rest = stream::iter(read).chain(body.into_data_stream());
+            return Err(Body::from_stream(rest));
+        }
+        buffered.extend_from_slice(&data);
+    }
+    Ok(Bytes::from(buffered))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    fn chunked(chunks: &'static [&'static str]) -> Body {
+        Body::from_stream(stream::iter(
+            chunks.iter().map(|chunk| Ok::<_, axum::Error>(*chunk)),
+        ))
+    }
+
+    fn nested(original: &str, path: &str) -> Parts {
+        let mut req = Request::get(path).body(()).unwrap();
+        req.extensions_mut()
+            .insert(OriginalUri(original.parse().unwrap()));
+        req.into_parts().0
+    }
+
+    #[test]
+    fn finds_mount_paths() {
+        let (parts, _) = Request::get("/users/1").body(()).unwrap().into_parts();
+        assert_eq!(mount_path(&parts), ("", "/users/1"));
+        assert_eq!(
+            mount_path(&nested("/users/1", "/users/1")),
+            ("", "/users/1")
+        );
+        assert_eq!(
+            mount_path(&nested("/api/users/1?x=1", "/users/1?x=1")),
+            ("/api", "/api/users/1")
+        );
+        assert_eq!(mount_path(&nested("/api", "/")), ("/api", "/api"));
+        assert_eq!(mount_path(&nested("/api/", "/")), ("/api", "/api/"));
+    }
+
+    #[test]
+    fn only_oversized_bodies_are_too_large() {
+        let error = block_on(axum::body::to_bytes(Body::from("abcd"), 3)).unwrap_err();
+        assert_eq!(body_error_status(&error), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let failing = Body::from_stream(stream::iter([Err::<Bytes, _>(std::io::Error::other(
+            "reset",
+        ))]));
+        let error = block_on(axum::body::to_bytes(failing, 3)).unwrap_err();
+        assert_eq!(body_error_status(&error), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn compares_json_bodies() {
+        let (a, b) = (br#"{"a": 1, "b": [2]}"#, br#"{"b":[2],"a":1}"#);
+        assert!(bodies_equal(a, b, true));
+        assert!(!bodies_equal(a, b, false));
+        assert!(!bodies_equal(a, br#"{"a": 1}"#, true));
+        assert!(bodies_equal(b"not json", b"not json", true));
+        assert!(!bodies_equal(b"not json", b"not  json", true));
+    }
+
+    #[test]
+    fn buffers_bodies_within_the_limit() {
+        let body = block_on(buffer_body(chunked(&["ab", "cd"]), 4)).unwrap();
+        assert_eq!(body, "abcd");
+    }
+
+    #[test]
+    fn gives_back_bodies_over_the_limit() {
+        let body = block_on(buffer_body(chunked(&["ab", "cd", "ef"]), 3)).unwrap_err();
+        let body = block_on(axum::body::to_bytes(body, usize::MAX)).unwrap();
+        assert_eq!(body, "abcdef");
+
+        let body = block_on(buffer_body(Body::from("abcdef"), 3)).unwrap_err();
+        let body = block_on(axum::body::to_bytes(body, usize::MAX)).unwrap();
+        assert_eq!(body, "abcdef");
+    }
+}
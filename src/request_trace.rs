@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use axum::{
+    extract::MatchedPath,
+    http::{request::Parts, StatusCode},
+};
+
+/// Tracks the progress of a request forwarded to the ASGI application.
+///
+/// With the `tracing` feature every request gets an `asgi_request` span with the method, route
+/// and status, and the steps of the ASGI message exchange are recorded as events in that span.
+/// Without the feature this does nothing.
+pub(crate) struct RequestTrace {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "tracing")]
+    start: std::time::Instant,
+    #[cfg(feature = "tracing")]
+    chunks: usize,
+    #[cfg(feature = "tracing")]
+    bytes: usize,
+    #[cfg(feature = "tracing")]
+    complete: bool,
+}
+
+impl RequestTrace {
+    pub(crate) fn new(parts: &Parts) -> RequestTrace {
+        let _route = parts
+            .extensions
+            .get::<MatchedPath>()
+            .map(|matched| matched.as_str())
+            .unwrap_or("fallback");
+        RequestTrace {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "asgi_request",
+                method = %parts.method,
+                route = _route,
+                path = parts.uri.path(),
+                status = tracing::field::Empty,
+            ),
+            #[cfg(feature = "tracing")]
+            start: std::time::Instant::now(),
+            #[cfg(feature = "tracing")]
+            chunks: 0,
+            #[cfg(feature = "tracing")]
+            bytes: 0,
+            #[cfg(feature = "tracing")]
+            complete: false,
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+
+    pub(crate) fn gil_acquired(&self, _waited: Duration) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            parent: &self.span,
+            waited_us = _waited.as_micros() as u64,
+            "acquired GIL"
+        );
+    }
+
+    pub(crate) fn scope_built(&self, _elapsed: Duration) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            parent: &self.span,
+            elapsed_us = _elapsed.as_micros() as u64,
+            "built scope"
+        );
+    }
+
+    pub(crate) fn response_start(&self, _status: u16) {
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("status", _status);
+            tracing::debug!(
+                parent: &self.span,
+                status = _status,
+                elapsed_us = self.start.elapsed().as_micros() as u64,
+                "http.response.start"
+            );
+        }
+    }
+
+    pub(crate) fn body_chunk(&mut self, _len: usize) {
+        #[cfg(feature = "tracing")]
+        {
+            self.chunks += 1;
+            self.bytes += _len;
+        }
+    }
+
+    pub(crate) fn complete(&mut self, _status: StatusCode) {
+        #[cfg(feature = "tracing")]
+        {
+            self.complete = true;
+            self.span.record("status", _status.as_u16());
+            tracing::debug!(
+                parent: &self.span,
+                chunks = self.chunks,
+                bytes = self.bytes,
+                elapsed_us = self.start.elapsed().as_micros() as u64,
+                "http.response.body complete"
+            );
+        }
+    }
+}
+
+impl Drop for RequestTrace {
+    fn drop(&mut self) {
+        #[cfg(feature = "tracing")]
+        if !self.complete {
+            tracing::info!(
+                parent: &self.span,
+                chunks = self.chunks,
+                bytes = self.bytes,
+                elapsed_us = self.start.elapsed().as_micros() as u64,
+                "client disconnected before the response completed"
+            );
+        }
+    }
+}
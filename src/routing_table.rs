@@ -0,0 +1,348 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    handler::Handler,
+    http::{Method, Request},
+    response::Response,
+};
+use futures::future::{BoxFuture, FutureExt};
+use tokio::task::JoinHandle;
+use tower::{Layer, Service};
+
+use crate::asgi::{AsgiHandler, Backend};
+
+type Routes = HashMap<(Option<Method>, String), Backend>;
+
+/// A runtime reloadable table deciding whether a route is served by rust or by the ASGI
+/// application.
+///
+/// The table maps axum route patterns (as passed to `Router::route`, e.g. `/users/{id}`),
+/// optionally limited to a single method, onto a backend. Routes that aren't in the table are
+/// served by the router as usual. Updates replace the whole table at once, so requests never
+/// see a partially applied update.
+///
+/// The text format has one route per line, with an optional method (`*` or omitted for all
+/// methods), the route pattern and the backend (`rust` or `asgi`). Empty lines and lines
+/// starting with `#` are ignored:
+///
+/// ```text
+/// GET /users/{id}  asgi
+/// *   /orders      rust
+///     /health      rust
+/// ```
+#[derive(Clone, Default)]
+pub struct RoutingTable {
+    routes: Arc<RwLock<Arc<Routes>>>,
+}
+
+/// Returned when parsing a routing table fails
+#[derive(Debug)]
+pub struct InvalidRoutingTable {
+    line: usize,
+    reason: String,
+}
+
+impl fmt::Display for InvalidRoutingTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid routing table on line {}: {}",
+            self.line, self.reason
+        )
+    }
+}
+
+impl std::error::Error for InvalidRoutingTable {}
+
+impl RoutingTable {
+    pub fn new() -> RoutingTable {
+        RoutingTable::default()
+    }
+
+    /// Create a routing table from a file in the text format
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<RoutingTable> {
+        let table = RoutingTable::new();
+        table.reload_from_file(path)?;
+        Ok(table)
+    }
+
+    /// Replace the routes with the contents of a file in the text format
+    pub fn reload_from_file(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let contents = std::fs::read_to_string(path)?;
+        self.update_from_str(&contents)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Replace the routes with the routes in the text format
+    pub fn update_from_str(&self, table: &str) -> Result<(), InvalidRoutingTable> {
+        let mut routes = Routes::new();
+        for (index, line) in table.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| InvalidRoutingTable {
+                line: index + 1,
+                reason: reason.to_string(),
+            };
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            let (method, path, backend) = match parts[..] {
+                [path, backend] => ("*", path, backend),
+                [method, path, backend] => (method, path, backend),
+                _ => return Err(invalid("expected `[METHOD] PATH BACKEND`")),
+            };
+            let method = match method {
+                "*" => None,
+                method => Some(
+                    Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                        .map_err(|_| invalid("invalid method"))?,
+                ),
+            };
+            if !path.starts_with('/') {
+                return Err(invalid("paths must start with `/`"));
+            }
+            let backend = backend
+                .parse::<Backend>()
+                .map_err(|_| invalid("backend must be `rust` or `asgi`"))?;
+            routes.insert((method, path.to_string()), backend);
+        }
+        self.replace(routes);
+        Ok(())
+    }
+
+    /// Replace the routes with the given `(method, path, backend)` entries, where a `None`
+    /// method matches all methods
+    pub fn update<I>(&self, routes: I)
+    where
+        I: IntoIterator<Item = (Option<Method>, String, Backend)>,
+    {
+        self.replace(
+            routes
+                .into_iter()
+                .map(|(method, path, backend)| ((method, path), backend))
+                .collect(),
+        );
+    }
+
+    /// The current `(method, path, backend)` entries
+    pub fn routes(&self) -> Vec<(Option<Method>, String, Backend)> {
+        let routes = self.current();
+        let mut routes = routes
+            .iter()
+            .map(|((method, path), backend)| (method.clone(), path.clone(), *backend))
+            .collect::<Vec<_>>();
+        routes.sort_by(|a, b| {
+            (&a.1, a.0.as_ref().map(Method::as_str)).cmp(&(&b.1, b.0.as_ref().map(Method::as_str)))
+        });
+        routes
+    }
+
+    /// Reload the table from `path` whenever the file's modification time changes, checking
+    /// every `interval`. Failed reloads keep the current routes.
+    ///
+    /// The file is checked on tokio's blocking pool. The watcher runs until the returned handle
+    /// is aborted.
+    pub fn watch_file(&self, path: impl Into<PathBuf>, interval: Duration) -> JoinHandle<()> {
+        let table = self.clone();
+        let path = Arc::new(path.into());
+        tokio::spawn(async move {
+            let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+            let mut last_modified: Option<SystemTime> = {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || modified(&path))
+                    .await
+                    .unwrap_or(None)
+            };
+            loop {
+                tokio::time::sleep(interval).await;
+                let (table, file) = (table.clone(), path.clone());
+                let last = last_modified;
+                let reload = tokio::task::spawn_blocking(move || {
+                    let current = modified(&file);
+                    let result = (current != last).then(|| table.reload_from_file(&*file));
+                    (current, result)
+                });
+                let Ok((current, result)) = reload.await else {
+                    continue;
+                };
+                last_modified = current;
+                match result {
+                    None => {}
+                    Some(Err(_e)) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::error!(
+                            "failed to reload routing table from {}: {_e}",
+                            path.display()
+                        );
+                    }
+                    Some(Ok(())) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::info!("reloaded routing table from {}", path.display());
+                    }
+                }
+            }
+        })
+    }
+
+    /// A layer for the rust router that sends the routes marked as `asgi` to the ASGI application
+    pub fn layer(&self, asgi: AsgiHandler) -> RoutingTableLayer {
+        RoutingTableLayer {
+            table: self.clone(),
+            asgi,
+        }
+    }
+
+    fn replace(&self, routes: Routes) {
+        // the lock is only held to swap the pointer, so a poisoned lock still holds valid routes
+        let mut current = self.routes.write().unwrap_or_else(|e| e.into_inner());
+        *current = Arc::new(routes);
+    }
+
+    fn current(&self) -> Arc<Routes> {
+        self.routes
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn backend(&self, method: &Method, path: &str) -> Option<Backend> {
+        let routes = self.current();
+        routes
+            .get(&(Some(method.clone()), path.to_string()))
+            .or_else(|| routes.get(&(None, path.to_string())))
+            .copied()
+    }
+}
+
+/// Layer created by [`RoutingTable::layer`].
+///
+/// This must be added with `Router::layer` or `Router::route_layer` after all the routes, so it
+/// sees the matched route of every request.
+#[derive(Clone)]
+pub struct RoutingTableLayer {
+    table: RoutingTable,
+    asgi: AsgiHandler,
+}
+
+impl<S> Layer<S> for RoutingTableLayer {
+    type Service = RoutingTableService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RoutingTableService {
+            inner,
+            table: self.table.clone(),
+            asgi: self.asgi.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RoutingTableService<S> {
+    inner: S,
+    table: RoutingTable,
+    asgi: AsgiHandler,
+}
+
+impl<S> Service<Request<Body>> for RoutingTableService<S>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|matched| matched.as_str().to_string());
+        let backend = route
+            .as_deref()
+            .and_then(|route| self.table.backend(req.method(), route));
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            method = %req.method(),
+            route = route.as_deref().unwrap_or("fallback"),
+            backend = backend.map(|backend| backend.as_str()).unwrap_or("router"),
+            "routing table dispatch"
+        );
+        match backend {
+            Some(Backend::Asgi) => Handler::<AsgiHandler, ()>::call(self.asgi.clone(), req, ())
+                .map(Ok)
+                .boxed(),
+            Some(Backend::Rust) | None => self.inner.call(req).boxed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_routes() {
+        let table = RoutingTable::new();
+        table
+            .update_from_str(
+                "# comment\n\nGET /users/{id}  asgi\n*   /orders rust\n    /health rust\npost /users asgi\n",
+            )
+            .unwrap();
+        assert_eq!(
+            table.routes(),
+            vec![
+                (None, "/health".to_string(), Backend::Rust),
+                (None, "/orders".to_string(), Backend::Rust),
+                (Some(Method::POST), "/users".to_string(), Backend::Asgi),
+                (Some(Method::GET), "/users/{id}".to_string(), Backend::Asgi),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        let table = RoutingTable::new();
+        table.update_from_str("/health rust").unwrap();
+        for (contents, line) in [
+            ("/health", 1),
+            ("\nGET /health rust extra", 2),
+            ("health rust", 1),
+            ("/health go", 1),
+            ("G(T /health rust", 1),
+        ] {
+            let error = table.update_from_str(contents).unwrap_err();
+            assert_eq!(error.line, line, "{contents:?}");
+        }
+        // failed updates keep the current routes
+        assert_eq!(
+            table.routes(),
+            vec![(None, "/health".to_string(), Backend::Rust)]
+        );
+    }
+
+    #[test]
+    fn method_routes_take_precedence() {
+        let table = RoutingTable::new();
+        table.update([
+            (None, "/users".to_string(), Backend::Rust),
+            (Some(Method::POST), "/users".to_string(), Backend::Asgi),
+        ]);
+        assert_eq!(table.backend(&Method::GET, "/users"), Some(Backend::Rust));
+        assert_eq!(table.backend(&Method::POST, "/users"), Some(Backend::Asgi));
+        assert_eq!(table.backend(&Method::GET, "/orders"), None);
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::ConnectInfo,
+    http::{HeaderName, HeaderValue, Method, Request, Version},
+    Router,
+};
+use http_body_util::BodyExt;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+    types::{PyBytes, PyDict, PyList},
+};
+use tower::ServiceExt;
+
+/// Characters that are percent encoded when building a path from the decoded `path` of a scope
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// An ASGI application dispatching requests into the rust router in process, so python code
+/// can call endpoints that have moved to rust without a round trip through the network.
+///
+/// It can be mounted in a Starlette (or FastAPI) application, or called directly with an http
+/// scope. The `root_path` of the scope is stripped from the path, so mounting it under `/rust`
+/// sends `/rust/users` to the `/users` route of the router. Requests that aren't handled by a
+/// rust route end up in the router's fallback, i.e. usually back in the python application.
+///
+/// The router usually isn't built until the server starts, so python gets a handle through
+/// `ServerContext.rust_app()` up front, and the router is set once it exists:
+///
+/// ```rust,ignore
+/// let rust_app = RustApp::new();
+/// let ctx = parviocula::create_server_context(app, Box::new({
+///     let rust_app = rust_app.clone();
+///     move |asgi: AsgiHandler, rx| {
+///         let router = Router::new().route("/users", get(get_users)).fallback(asgi);
+///         rust_app.set_router(router.clone());
+///         async move { /* serve the router */ }
+///     }
+/// }));
+/// Python::with_gil(|py| ctx.borrow_mut(py).set_rust_app(rust_app));
+/// ```
+#[pyclass(frozen)]
+#[derive(Clone, Default)]
+pub struct RustApp {
+    router: Arc<RwLock<Option<Router>>>,
+}
+
+impl RustApp {
+    pub fn new() -> RustApp {
+        RustApp::default()
+    }
+
+    /// Set the router requests are dispatched to
+    pub fn set_router(&self, router: Router) {
+        *self.router.write().unwrap_or_else(|e| e.into_inner()) = Some(router);
+    }
+
+    fn router(&self) -> Option<Router> {
+        self.router
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+#[pymethods]
+impl RustApp {
+    fn __call__<'py>(
+        &self,
+        py: Python<'py>,
+        scope: Bound<'py, PyDict>,
+        receive: PyObject,
+        send: PyObject,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let scope_type = scope
+            .get_item("type")?
+            .ok_or_else(|| PyErr::new::<PyValueError, _>("missing scope type"))?
+            .extract::<String>()?;
+        match scope_type.as_str() {
+            "lifespan" => pyo3_async_runtimes::tokio::future_into_py(py, lifespan(receive, send)),
+            "http" => {
+                let router = self.router().ok_or_else(|| {
+                    PyErr::new::<PyRuntimeError, _>("the rust router hasn't been set yet")
+                })?;
+                let req = request_from_scope(&scope)?;
+                pyo3_async_runtimes::tokio::future_into_py(py, async move {
+                    let Some(body) = receive_body(&receive).await? else {
+                        return Ok(Python::with_gil(|py| py.None()));
+                    };
+                    let req = req.map(|_| Body::from(body));
+                    let response = match router.oneshot(req).await {
+                        Ok(response) => response,
+                        Err(infallible) => match infallible {},
+                    };
+                    let (parts, mut body) = response.into_parts();
+                    let start = Python::with_gil(|py| {
+                        let message = PyDict::new(py);
+                        message.set_item("type", "http.response.start")?;
+                        message.set_item("status", parts.status.as_u16())?;
+                        let headers = parts
+                            .headers
+                            .iter()
+                            .map(|(name, value)| {
+                                (
+                                    PyBytes::new(py, name.as_str().as_bytes()),
+                                    PyBytes::new(py, value.as_bytes()),
+                                )
+                            })
+                            .collect::<Vec<_>>();
+                        message.set_item("headers", PyList::new(py, headers)?)?;
+                        Ok::<_, PyErr>(message.unbind())
+                    })?;
+                    call_async(&send, Some(start)).await?;
+                    while let Some(frame) = body.frame().await {
+                        let frame =
+                            frame.map_err(|e| PyErr::new::<PyRuntimeError, _>(e.to_string()))?;
+                        if let Ok(data) = frame.into_data() {
+                            call_async(&send, Some(body_message(data, true)?)).await?;
+                        }
+                    }
+                    call_async(&send, Some(body_message(Bytes::new(), false)?)).await?;
+                    Ok(Python::with_gil(|py| py.None()))
+                })
+            }
+            _ => Err(PyErr::new::<PyValueError, _>(format!(
+                "unsupported scope type {scope_type}"
+            ))),
+        }
+    }
+}
+
+fn body_message(body: Bytes, more_body: bool) -> PyResult<Py<PyDict>> {
+    Python::with_gil(|py| {
+        let message = PyDict::new(py);
+        message.set_item("type", "http.response.body")?;
+        message.set_item("body", PyBytes::new(py, &body))?;
+        message.set_item("more_body", more_body)?;
+        Ok(message.unbind())
+    })
+}
+
+/// Call an ASGI `send` or `receive` callable and await the result on the running event loop
+async fn call_async(callable: &PyObject, message: Option<Py<PyDict>>) -> PyResult<PyObject> {
+    let fut = Python::with_gil(|py| {
+        let awaitable = match message {
+            Some(message) => callable.call1(py, (message,))?,
+            None => callable.call0(py)?,
+        };
+        pyo3_async_runtimes::tokio::into_future(awaitable.into_bound(py))
+    })?;
+    fut.await
+}
+
+/// Read the whole request body, or `None` if the client disconnected
+async fn receive_body(receive: &PyObject) -> PyResult<Option<Bytes>> {
+    let mut body = Vec::new();
+    loop {
+        let message = call_async(receive, None).await?;
+        let more_body = Python::with_gil(|py| {
+            let message = message.bind(py);
+            let message_type = message.get_item("type")?.extract::<String>()?;
+            if message_type == "http.disconnect" {
+                return Ok(None);
+            }
+            if let Ok(chunk) = message.get_item("body") {
+                if !chunk.is_none() {
+                    body.extend(chunk.extract::<Vec<u8>>()?);
+                }
+            }
+            let more_body = match message.get_item("more_body") {
+                Ok(more_body) => more_body.extract::<bool>()?,
+                Err(_) => false,
+            };
+            Ok::<_, PyErr>(Some(more_body))
+        })?;
+        match more_body {
+            None => return Ok(None),
+            Some(true) => continue,
+            Some(false) => return Ok(Some(Bytes::from(body))),
+        }
+    }
+}
+
+/// Answer the lifespan messages, the rust router doesn't have any startup or shutdown of its own
+async fn lifespan(receive: PyObject, send: PyObject) -> PyResult<PyObject> {
+    loop {
+        let message = call_async(&receive, None).await?;
+        let message_type =
+            Python::with_gil(|py| message.bind(py).get_item("type")?.extract::<String>())?;
+        let (reply, done) = match message_type.as_str() {
+            "lifespan.startup" => ("lifespan.startup.complete", false),
+            "lifespan.shutdown" => ("lifespan.shutdown.complete", true),
+            _ => continue,
+        };
+        let reply = Python::with_gil(|py| {
+            let message = PyDict::new(py);
+            message.set_item("type", reply)?;
+            Ok::<_, PyErr>(message.unbind())
+        })?;
+        call_async(&send, Some(reply)).await?;
+        if done {
+            return Ok(Python::with_gil(|py| py.None()));
+        }
+    }
+}
+
+fn request_from_scope(scope: &Bound<'_, PyDict>) -> PyResult<Request<()>> {
+    let invalid = |reason: String| PyErr::new::<PyValueError, _>(reason);
+    let get_str = |key: &str| -> PyResult<Option<String>> {
+        scope
+            .get_item(key)?
+            .filter(|value| !value.is_none())
+            .map(|value| value.extract::<String>())
+            .transpose()
+    };
+
+    let method = get_str("method")?.unwrap_or_else(|| "GET".to_string());
+    let path = match scope.get_item("raw_path")?.filter(|value| !value.is_none()) {
+        Some(raw_path) => String::from_utf8(raw_path.extract::<Vec<u8>>()?)
+            .map_err(|_| invalid("raw_path isn't valid utf-8".to_string()))?,
+        None => utf8_percent_encode(&get_str("path")?.unwrap_or_default(), PATH).to_string(),
+    };
+    let root_path = get_str("root_path")?.unwrap_or_default();
+    let path = match path.strip_prefix(root_path.as_str()) {
+        Some(rest) if !root_path.is_empty() && rest.is_empty() => "/",
+        Some(rest) if !root_path.is_empty() && rest.starts_with('/') => rest,
+        _ => path.as_str(),
+    };
+    let query = match scope.get_item("query_string")? {
+        Some(query) if !query.is_none() => String::from_utf8(query.extract::<Vec<u8>>()?)
+            .map_err(|_| invalid("query_string isn't valid utf-8".to_string()))?,
+        _ => String::new(),
+    };
+    let uri = if query.is_empty() {
+        path.to_string()
+    } else {
+        format!("{path}?{query}")
+    };
+
+    let mut req = Request::new(());
+    *req.method_mut() = Method::from_bytes(method.as_bytes())
+        .map_err(|_| invalid(format!("invalid method {method}")))?;
+    *req.uri_mut() = uri
+        .parse()
+        .map_err(|_| invalid(format!("invalid path {uri}")))?;
+    *req.version_mut() = match get_str("http_version")?.as_deref() {
+        Some("1.0") => Version::HTTP_10,
+        Some("2") => Version::HTTP_2,
+        _ => Version::HTTP_11,
+    };
+    if let Some(headers) = scope.get_item("headers")? {
+        for header in headers.try_iter()? {
+            // headers may be lists or tuples
+            let header = header?;
+            let (name, value) = (
+                header.get_item(0)?.extract::<Vec<u8>>()?,
+                header.get_item(1)?.extract::<Vec<u8>>()?,
+            );
+            req.headers_mut().append(
+                HeaderName::from_bytes(&name).map_err(|e| invalid(e.to_string()))?,
+                HeaderValue::from_bytes(&value).map_err(|e| invalid(e.to_string()))?,
+            );
+        }
+    }
+    if let Some(client) = scope.get_item("client")?.filter(|value| !value.is_none()) {
+        let (host, port) = (
+            client.get_item(0)?.extract::<String>()?,
+            client.get_item(1)?.extract::<u16>()?,
+        );
+        if let Ok(ip) = host.parse() {
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::new(ip, port)));
+        }
+    }
+    Ok(req)
+}
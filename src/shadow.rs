@@ -0,0 +1,325 @@
+use std::{fmt, future::Future, marker::PhantomData, pin::Pin, sync::Arc};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    handler::Handler,
+    http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, Uri},
+    response::Response,
+};
+use futures::future::BoxFuture;
+
+use crate::{
+    asgi::{AsgiHandler, Backend},
+    request::{bodies_equal, buffer_body, copy_request, DEFAULT_MAX_BODY_SIZE},
+};
+
+/// A difference between the responses of the rust handler and the ASGI application.
+#[derive(Debug)]
+pub struct ShadowDiff {
+    pub method: Method,
+    pub uri: Uri,
+    /// The backend whose response was sent to the client
+    pub primary: Backend,
+    /// The primary and shadow status, if they differ
+    pub status: Option<(StatusCode, StatusCode)>,
+    /// The headers that differ, with the primary and shadow values
+    pub headers: Vec<(HeaderName, Vec<HeaderValue>, Vec<HeaderValue>)>,
+    /// The primary and shadow bodies, if they differ
+    pub body: Option<(Bytes, Bytes)>,
+}
+
+impl ShadowDiff {
+    pub fn is_empty(&self) -> bool {
+        self.status.is_none() && self.headers.is_empty() && self.body.is_none()
+    }
+}
+
+impl fmt::Display for ShadowDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} (primary: {})",
+            self.method,
+            self.uri,
+            self.primary.as_str()
+        )?;
+        if let Some((primary, shadow)) = &self.status {
+            write!(f, ", status {primary} != {shadow}")?;
+        }
+        for (name, primary, shadow) in &self.headers {
+            write!(f, ", header {name} {primary:?} != {shadow:?}")?;
+        }
+        if let Some((primary, shadow)) = &self.body {
+            write!(
+                f,
+                ", body differs ({} bytes != {} bytes)",
+                primary.len(),
+                shadow.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+type DiffHook = dyn Fn(&ShadowDiff) + Send + Sync;
+
+#[derive(Clone)]
+struct ShadowConfig {
+    primary: Backend,
+    methods: Vec<Method>,
+    ignored_headers: Vec<HeaderName>,
+    json_body: bool,
+    max_body_size: usize,
+    hook: Option<Arc<DiffHook>>,
+}
+
+/// Serves a route from one backend while sending a copy of each request to the other backend
+/// in the background, reporting any differences between the two responses.
+///
+/// By default the ASGI application is the primary and the rust handler the shadow, so a new
+/// rust handler can be checked against production traffic without affecting clients.
+/// Differences are passed to the hook set with [`Shadow::with_diff_hook`], or otherwise logged
+/// as a `tracing` warning when the `tracing` feature is enabled.
+///
+/// Only safe methods (`GET`, `HEAD`, `OPTIONS` and `TRACE`) are shadowed by default, as the
+/// request runs on both backends, other requests are only sent to the primary. Writes have to be
+/// opted in to with [`Shadow::with_methods`].
+///
+/// Request and response bodies are buffered in memory so they can be sent to both backends and
+/// compared. Requests or primary responses over the maximum body size aren't compared and are
+/// passed through untouched.
+///
+/// ```rust,ignore
+/// Router::new().route("/users", get(Shadow::new(get_users, asgi.clone())))
+/// ```
+pub struct Shadow<H, T> {
+    rust: H,
+    asgi: AsgiHandler,
+    config: Arc<ShadowConfig>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<H: Clone, T> Clone for Shadow<H, T> {
+    fn clone(&self) -> Self {
+        Shadow {
+            rust: self.rust.clone(),
+            asgi: self.asgi.clone(),
+            config: self.config.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<H, T> Shadow<H, T> {
+    pub fn new(rust: H, asgi: AsgiHandler) -> Shadow<H, T> {
+        Shadow {
+            rust,
+            asgi,
+            config: Arc::new(ShadowConfig {
+                primary: Backend::Asgi,
+                methods: vec![Method::GET, Method::HEAD, Method::OPTIONS, Method::TRACE],
+                ignored_headers: vec![
+                    HeaderName::from_static("date"),
+                    HeaderName::from_static("server"),
+                ],
+                json_body: false,
+                max_body_size: DEFAULT_MAX_BODY_SIZE,
+                hook: None,
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Set which backend's response is sent to the client
+    pub fn with_primary(mut self, primary: Backend) -> Self {
+        Arc::make_mut(&mut self.config).primary = primary;
+        self
+    }
+
+    /// Set the methods of the requests sent to both backends, e.g. to opt in to shadowing `PUT`
+    /// requests when the shadow has no side effects. Defaults to `GET`, `HEAD`, `OPTIONS` and
+    /// `TRACE`.
+    pub fn with_methods<I>(mut self, methods: I) -> Self
+    where
+        I: IntoIterator<Item = Method>,
+    {
+        Arc::make_mut(&mut self.config).methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Set the headers that are not compared. Defaults to `date` and `server`.
+    pub fn with_ignored_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        Arc::make_mut(&mut self.config).ignored_headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Compare the primary and shadow bodies as JSON values when both are valid JSON
+    pub fn with_json_body_comparison(mut self, json_body: bool) -> Self {
+        Arc::make_mut(&mut self.config).json_body = json_body;
+        self
+    }
+
+    /// Set the maximum size of the bodies buffered for comparison. Requests and primary responses
+    /// over it are passed through without running the shadow. Defaults to 4MB.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        Arc::make_mut(&mut self.config).max_body_size = max_body_size;
+        self
+    }
+
+    /// Call `hook` for every request where the responses differ
+    pub fn with_diff_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&ShadowDiff) + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.config).hook = Some(Arc::new(hook));
+        self
+    }
+}
+
+impl<H, T, S> Handler<T, S> for Shadow<H, T>
+where
+    H: Handler<T, S>,
+    T: 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    type Future = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+    fn call(self, req: Request<Body>, state: S) -> Self::Future {
+        Box::pin(async move {
+            let config = self.config;
+            let request = (req.method().clone(), req.uri().clone());
+            let (req, shadow_req) = if config.methods.contains(req.method()) {
+                let (parts, body) = req.into_parts();
+                match buffer_body(body, config.max_body_size).await {
+                    Ok(body) => {
+                        let shadow_req = copy_request(&parts, &body);
+                        (
+                            Request::from_parts(parts, Body::from(body)),
+                            Some(shadow_req),
+                        )
+                    }
+                    Err(body) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!("request body too large to shadow, skipping comparison");
+                        (Request::from_parts(parts, body), None)
+                    }
+                }
+            } else {
+                (req, None)
+            };
+
+            let rust = |req, state| -> BoxFuture<'static, Response> {
+                Box::pin(self.rust.call(req, state))
+            };
+            let asgi = |req, state| -> BoxFuture<'static, Response> {
+                Box::pin(Handler::<AsgiHandler, S>::call(self.asgi, req, state))
+            };
+            let (primary, shadow) = match config.primary {
+                Backend::Rust => (
+                    rust(req, state.clone()),
+                    shadow_req.map(|req| asgi(req, state)),
+                ),
+                Backend::Asgi => (
+                    asgi(req, state.clone()),
+                    shadow_req.map(|req| rust(req, state)),
+                ),
+            };
+            let Some(shadow) = shadow else {
+                return primary.await;
+            };
+
+            let (primary_parts, primary_body) = primary.await.into_parts();
+            let primary_body = match buffer_body(primary_body, config.max_body_size).await {
+                Ok(body) => body,
+                Err(body) => {
+                    // the shadow hasn't been polled yet, so dropping it skips it entirely
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("primary response body too large to compare, skipping it");
+                    return Response::from_parts(primary_parts, body);
+                }
+            };
+
+            let primary_status = primary_parts.status;
+            let primary_headers = primary_parts.headers.clone();
+            let primary_copy = primary_body.clone();
+            tokio::spawn(async move {
+                let (shadow_parts, shadow_body) = shadow.await.into_parts();
+                let shadow_body = match to_bytes(shadow_body, config.max_body_size).await {
+                    Ok(body) => body,
+                    Err(_e) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!("failed to buffer shadow response body: {_e}");
+                        return;
+                    }
+                };
+                let diff = config.diff(
+                    request,
+                    (primary_status, &primary_headers, primary_copy),
+                    (shadow_parts.status, &shadow_parts.headers, shadow_body),
+                );
+                if !diff.is_empty() {
+                    config.report(&diff);
+                }
+            });
+
+            Response::from_parts(primary_parts, Body::from(primary_body))
+        })
+    }
+}
+
+impl ShadowConfig {
+    fn diff(
+        &self,
+        (method, uri): (Method, Uri),
+        (primary_status, primary_headers, primary_body): (StatusCode, &HeaderMap, Bytes),
+        (shadow_status, shadow_headers, shadow_body): (StatusCode, &HeaderMap, Bytes),
+    ) -> ShadowDiff {
+        let mut headers = Vec::new();
+        for name in primary_headers.keys().chain(shadow_headers.keys()) {
+            if self.ignored_headers.contains(name) || headers.iter().any(|(n, _, _)| n == name) {
+                continue;
+            }
+            let primary = primary_headers
+                .get_all(name)
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>();
+            let shadow = shadow_headers
+                .get_all(name)
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>();
+            if primary != shadow {
+                headers.push((name.clone(), primary, shadow));
+            }
+        }
+
+        let body_equal = bodies_equal(&primary_body, &shadow_body, self.json_body);
+
+        ShadowDiff {
+            method,
+            uri,
+            primary: self.primary,
+            status: (primary_status != shadow_status).then_some((primary_status, shadow_status)),
+            headers,
+            body: (!body_equal).then_some((primary_body, shadow_body)),
+        }
+    }
+
+    fn report(&self, diff: &ShadowDiff) {
+        if let Some(hook) = &self.hook {
+            hook(diff);
+        } else {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                method = %diff.method,
+                uri = %diff.uri,
+                primary = diff.primary.as_str(),
+                "shadow response differs: {diff}"
+            );
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use axum::{
+    body::Body,
+    handler::Handler,
+    http::{header::COOKIE, HeaderMap, HeaderName, Request},
+    response::Response,
+};
+
+use crate::asgi::{AsgiHandler, Backend};
+
+/// The share of requests is stored in basis points (1/100th of a percent)
+const BASIS_POINTS: u32 = 10_000;
+
+/// Shared, runtime adjustable settings for a [`Split`].
+///
+/// Cloning the control gives a handle to the same settings, so it can be kept around (e.g. in
+/// an admin endpoint or a python callback) to change the share of traffic sent to rust without
+/// restarting the server.
+#[derive(Clone, Debug)]
+pub struct SplitControl {
+    inner: Arc<SplitState>,
+}
+
+#[derive(Debug)]
+struct SplitState {
+    basis_points: AtomicU32,
+    killed: AtomicBool,
+}
+
+impl SplitControl {
+    /// Create a control sending `percentage` (0 to 100) of the requests to the rust handler
+    pub fn new(percentage: f64) -> SplitControl {
+        SplitControl {
+            inner: Arc::new(SplitState {
+                basis_points: AtomicU32::new(to_basis_points(percentage)),
+                killed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    pub fn percentage(&self) -> f64 {
+        self.inner.basis_points.load(Ordering::Relaxed) as f64 / 100.0
+    }
+
+    pub fn set_percentage(&self, percentage: f64) {
+        self.inner
+            .basis_points
+            .store(to_basis_points(percentage), Ordering::Relaxed);
+    }
+
+    /// When killed, every request is sent to the ASGI application regardless of the percentage
+    pub fn set_killed(&self, killed: bool) {
+        self.inner.killed.store(killed, Ordering::Relaxed);
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.inner.killed.load(Ordering::Relaxed)
+    }
+
+    fn backend(&self, bucket: u64) -> Backend {
+        if self.is_killed() {
+            return Backend::Asgi;
+        }
+        let basis_points = self.inner.basis_points.load(Ordering::Relaxed);
+        if ((bucket % u64::from(BASIS_POINTS)) as u32) < basis_points {
+            Backend::Rust
+        } else {
+            Backend::Asgi
+        }
+    }
+}
+
+fn to_basis_points(percentage: f64) -> u32 {
+    (percentage.clamp(0.0, 100.0) * 100.0).round() as u32
+}
+
+#[derive(Clone, Debug)]
+enum StickyKey {
+    Header(HeaderName),
+    Cookie(String),
+}
+
+impl StickyKey {
+    fn value<'a>(&self, headers: &'a HeaderMap) -> Option<&'a [u8]> {
+        match self {
+            StickyKey::Header(name) => headers.get(name).map(|value| value.as_bytes()),
+            StickyKey::Cookie(name) => headers
+                .get_all(COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .filter_map(|cookie| cookie.trim().split_once('='))
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_bytes()),
+        }
+    }
+}
+
+/// FNV-1a, used for sticky keys as it gives the same bucket across restarts and instances
+fn stable_hash(value: &[u8]) -> u64 {
+    value.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The bucket of a request, the same for every request with the same sticky value
+fn bucket(sticky: Option<&StickyKey>, headers: &HeaderMap) -> u64 {
+    match sticky.and_then(|sticky| sticky.value(headers)) {
+        Some(value) => stable_hash(value),
+        None => fastrand::u64(..),
+    }
+}
+
+/// Sends a share of a route's requests to a rust handler, and the rest to the ASGI application.
+///
+/// Requests are assigned randomly, unless a sticky header or cookie is set, in which case all
+/// requests with the same value go to the same backend (requests without it are still assigned
+/// randomly). The share can be changed at runtime through the [`SplitControl`].
+///
+/// ```rust,ignore
+/// let control = SplitControl::new(1.0);
+/// Router::new().route(
+///     "/users",
+///     get(Split::new(get_users, asgi.clone(), control.clone()).with_sticky_cookie("session")),
+/// )
+/// ```
+pub struct Split<H, T> {
+    rust: H,
+    asgi: AsgiHandler,
+    control: SplitControl,
+    sticky: Option<StickyKey>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<H: Clone, T> Clone for Split<H, T> {
+    fn clone(&self) -> Self {
+        Split {
+            rust: self.rust.clone(),
+            asgi: self.asgi.clone(),
+            control: self.control.clone(),
+            sticky: self.sticky.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<H, T> Split<H, T> {
+    pub fn new(rust: H, asgi: AsgiHandler, control: SplitControl) -> Split<H, T> {
+        Split {
+            rust,
+            asgi,
+            control,
+            sticky: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Assign requests to a backend based on the value of the given header
+    pub fn with_sticky_header(mut self, name: HeaderName) -> Self {
+        self.sticky = Some(StickyKey::Header(name));
+        self
+    }
+
+    /// Assign requests to a backend based on the value of the given cookie
+    pub fn with_sticky_cookie(mut self, name: impl Into<String>) -> Self {
+        self.sticky = Some(StickyKey::Cookie(name.into()));
+        self
+    }
+}
+
+impl<H, T, S> Handler<T, S> for Split<H, T>
+where
+    H: Handler<T, S>,
+    T: 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    type Future = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+    fn call(self, req: Request<Body>, state: S) -> Self::Future {
+        let backend = self
+            .control
+            .backend(bucket(self.sticky.as_ref(), req.headers()));
+        match backend {
+            Backend::Rust => Box::pin(self.rust.call(req, state)),
+            Backend::Asgi => Handler::<AsgiHandler, S>::call(self.asgi, req, state),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    value.parse().expect("invalid header value"),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_sticky_values() {
+        let headers = headers(&[
+            ("x-user", "42"),
+            ("cookie", "theme=dark; session=abc"),
+            ("cookie", "other=1"),
+        ]);
+        let header = StickyKey::Header(HeaderName::from_static("x-user"));
+        assert_eq!(header.value(&headers), Some(&b"42"[..]));
+        let cookie = StickyKey::Cookie("session".to_string());
+        assert_eq!(cookie.value(&headers), Some(&b"abc"[..]));
+        let cookie = StickyKey::Cookie("other".to_string());
+        assert_eq!(cookie.value(&headers), Some(&b"1"[..]));
+        let missing = StickyKey::Cookie("sess".to_string());
+        assert_eq!(missing.value(&headers), None);
+    }
+
+    #[test]
+    fn sticky_values_keep_their_bucket() {
+        let sticky = StickyKey::Cookie("session".to_string());
+        let first = headers(&[("cookie", "session=abc")]);
+        let second = headers(&[("cookie", "theme=dark; session=abc")]);
+        assert_eq!(
+            bucket(Some(&sticky), &first),
+            bucket(Some(&sticky), &second)
+        );
+        assert_eq!(bucket(Some(&sticky), &first), stable_hash(b"abc"));
+        assert_ne!(stable_hash(b"abc"), stable_hash(b"abd"));
+    }
+
+    #[test]
+    fn buckets_by_percentage() {
+        let control = SplitControl::new(25.0);
+        assert_eq!(control.percentage(), 25.0);
+        assert_eq!(control.backend(0), Backend::Rust);
+        assert_eq!(control.backend(2_499), Backend::Rust);
+        assert_eq!(control.backend(2_500), Backend::Asgi);
+        assert_eq!(control.backend(9_999), Backend::Asgi);
+        assert_eq!(control.backend(10_000), Backend::Rust);
+
+        control.set_percentage(150.0);
+        assert_eq!(control.percentage(), 100.0);
+        assert_eq!(control.backend(9_999), Backend::Rust);
+        control.set_killed(true);
+        assert_eq!(control.backend(0), Backend::Asgi);
+        control.set_killed(false);
+        control.set_percentage(0.0);
+        assert_eq!(control.backend(0), Backend::Asgi);
+    }
+
+    #[test]
+    fn random_buckets_follow_the_percentage() {
+        let control = SplitControl::new(30.0);
+        let headers = HeaderMap::new();
+        let rust = (0..10_000)
+            .filter(|_| control.backend(bucket(None, &headers)) == Backend::Rust)
+            .count();
+        assert!(
+            (2_500..3_500).contains(&rust),
+            "{rust} requests sent to rust"
+        );
+    }
+}
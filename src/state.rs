@@ -0,0 +1,124 @@
+use std::{
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+};
+
+use axum::{
+    body::Body,
+    handler::Handler,
+    http::{Extensions, Request},
+    response::Response,
+};
+use pyo3::{prelude::*, types::PyDict};
+
+use crate::{asgi::AsgiHandler, extensions::ExtensionsConverter};
+
+/// Converts axum state into entries of `scope["state"]` (i.e. `request.state` in Starlette).
+pub trait ScopeState: Clone + Send + Sync + 'static {
+    fn to_scope_state<'py>(&self, py: Python<'py>, state: &Bound<'py, PyDict>) -> PyResult<()>;
+}
+
+impl<T: ScopeState> ScopeState for Arc<T> {
+    fn to_scope_state<'py>(&self, py: Python<'py>, state: &Bound<'py, PyDict>) -> PyResult<()> {
+        T::to_scope_state(self, py, state)
+    }
+}
+
+/// An [`AsgiHandler`] that passes the router's state to the ASGI application in
+/// `scope["state"]`, where the plain `AsgiHandler` ignores it.
+///
+/// By default the state is converted for every request, so python sees changes to it (e.g.
+/// feature flags behind a lock). With [`StatefulAsgiHandler::with_cached_state`] it's converted
+/// once, on the first request, and every request shares the same python objects.
+///
+/// ```rust,ignore
+/// #[derive(Clone)]
+/// struct AppState { flags: Arc<RwLock<HashMap<String, bool>>> }
+///
+/// impl ScopeState for AppState {
+///     fn to_scope_state<'py>(&self, py: Python<'py>, state: &Bound<'py, PyDict>) -> PyResult<()> {
+///         state.set_item("flags", self.flags.read().unwrap().clone())
+///     }
+/// }
+///
+/// Router::new()
+///     .fallback(StatefulAsgiHandler::new(asgi))
+///     .with_state(AppState { flags })
+/// ```
+pub struct StatefulAsgiHandler<S> {
+    asgi: AsgiHandler,
+    cached: Option<Arc<OnceLock<Py<PyDict>>>>,
+    _marker: PhantomData<fn() -> S>,
+}
+
+impl<S> Clone for StatefulAsgiHandler<S> {
+    fn clone(&self) -> Self {
+        StatefulAsgiHandler {
+            asgi: self.asgi.clone(),
+            cached: self.cached.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S> StatefulAsgiHandler<S> {
+    pub fn new(asgi: AsgiHandler) -> StatefulAsgiHandler<S> {
+        StatefulAsgiHandler {
+            asgi,
+            cached: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Convert the state once, on the first request, instead of for every request
+    pub fn with_cached_state(mut self) -> Self {
+        self.cached = Some(Arc::new(OnceLock::new()));
+        self
+    }
+}
+
+struct StateConverter<S> {
+    state: S,
+    cached: Option<Arc<OnceLock<Py<PyDict>>>>,
+}
+
+impl<S: ScopeState> ExtensionsConverter for StateConverter<S> {
+    fn convert<'py>(
+        &self,
+        py: Python<'py>,
+        _request: &Extensions,
+        state: &Bound<'py, PyDict>,
+        _extensions: &Bound<'py, PyDict>,
+    ) -> PyResult<()> {
+        let Some(cached) = &self.cached else {
+            return self.state.to_scope_state(py, state);
+        };
+        let converted = match cached.get() {
+            Some(converted) => converted.bind(py).clone(),
+            None => {
+                let converted = PyDict::new(py);
+                self.state.to_scope_state(py, &converted)?;
+                // if another request converted it in the meantime, its objects are kept
+                cached.get_or_init(|| converted.unbind()).bind(py).clone()
+            }
+        };
+        state.update(converted.as_mapping())
+    }
+}
+
+impl<S> Handler<StatefulAsgiHandler<S>, S> for StatefulAsgiHandler<S>
+where
+    S: ScopeState,
+{
+    type Future = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+    fn call(self, req: Request<Body>, state: S) -> Self::Future {
+        let asgi = self.asgi.with_extensions_converter(StateConverter {
+            state,
+            cached: self.cached,
+        });
+        Handler::<AsgiHandler, ()>::call(asgi, req, ())
+    }
+}
@@ -0,0 +1,162 @@
+use std::{future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{body::Body, extract::connect_info::ConnectInfo, http::Request, Router};
+use base64::Engine;
+use futures::future::Either;
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto::Builder, graceful::GracefulShutdown},
+};
+use pyo3::{
+    prelude::*,
+    types::{PyDict, PyList},
+};
+use tokio::net::TcpListener;
+use tokio_rustls::{
+    rustls::{ServerConfig, ServerConnection},
+    TlsAcceptor,
+};
+use tower::ServiceExt;
+
+/// How long to wait before accepting again after an accept error (e.g. too many open files)
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+/// How long a client may take to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Details of the TLS connection a request was received on.
+///
+/// Inserted into the request extensions by [`serve_tls`], so it is also available to axum
+/// handlers via `Extension<TlsInfo>`. The `AsgiHandler` uses it to report the `https` scheme
+/// and to populate the `tls` extension in the ASGI scope.
+#[derive(Clone, Debug)]
+pub struct TlsInfo {
+    /// The client certificate chain in DER format, leaf first. Empty if the client did not
+    /// present a certificate.
+    pub client_cert_chain: Vec<Vec<u8>>,
+    /// The negotiated TLS version, e.g. `0x0304` for TLS 1.3.
+    pub tls_version: Option<u16>,
+    /// The negotiated cipher suite as its IANA value.
+    pub cipher_suite: Option<u16>,
+}
+
+impl TlsInfo {
+    fn from_connection(conn: &ServerConnection) -> TlsInfo {
+        TlsInfo {
+            client_cert_chain: conn
+                .peer_certificates()
+                .map(|certs| certs.iter().map(|cert| cert.to_vec()).collect())
+                .unwrap_or_default(),
+            tls_version: conn.protocol_version().map(u16::from),
+            cipher_suite: conn
+                .negotiated_cipher_suite()
+                .map(|suite| u16::from(suite.suite())),
+        }
+    }
+
+    /// Build the `tls` scope extension
+    /// https://asgi.readthedocs.io/en/latest/specs/tls.html
+    pub(crate) fn to_extension<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let tls = PyDict::new(py);
+        tls.set_item("server_cert", py.None())?;
+        let chain = PyList::new(py, self.client_cert_chain.iter().map(|der| der_to_pem(der)))?;
+        tls.set_item("client_cert_chain", chain)?;
+        tls.set_item("client_cert_name", py.None())?;
+        tls.set_item("client_cert_error", py.None())?;
+        tls.set_item("tls_version", self.tls_version)?;
+        tls.set_item("cipher_suite", self.cipher_suite)?;
+        Ok(tls)
+    }
+}
+
+/// The spec requires certificates to be passed on as PEM encoded strings
+fn der_to_pem(der: &[u8]) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(der);
+    let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        // base64 output is always ascii
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str("-----END CERTIFICATE-----\n");
+    pem
+}
+
+/// Serve the router over TLS, terminating the connections with rustls.
+///
+/// This takes the place of `axum::serve(...).with_graceful_shutdown(...)` for servers that need
+/// to terminate TLS themselves. Each request gets [`TlsInfo`] and `ConnectInfo<SocketAddr>`
+/// inserted into its extensions. When `signal` completes no new connections are accepted, and
+/// this returns once all in flight connections have finished.
+///
+/// Like `axum::serve`, accepting pauses for a second after an accept error, so running out of
+/// file descriptors doesn't turn into a busy loop. Clients have 10 seconds to complete the TLS
+/// handshake.
+pub async fn serve_tls<F>(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    router: Router,
+    signal: F,
+) -> std::io::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let acceptor = TlsAcceptor::from(config);
+    let builder = Builder::new(TokioExecutor::new());
+    let graceful = GracefulShutdown::new();
+    let mut signal = std::pin::pin!(signal);
+
+    loop {
+        let accept = std::pin::pin!(listener.accept());
+        let (stream, remote_addr) = match futures::future::select(accept, signal.as_mut()).await {
+            Either::Left((Ok(conn), _)) => conn,
+            Either::Left((Err(_e), _)) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!("failed to accept connection: {_e}");
+                let sleep = std::pin::pin!(tokio::time::sleep(ACCEPT_ERROR_DELAY));
+                match futures::future::select(sleep, signal.as_mut()).await {
+                    Either::Left(_) => continue,
+                    Either::Right(_) => break,
+                }
+            }
+            Either::Right(_) => break,
+        };
+
+        let acceptor = acceptor.clone();
+        let builder = builder.clone();
+        let router = router.clone();
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(_e)) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!("TLS handshake with {remote_addr} failed: {_e}");
+                        return;
+                    }
+                    Err(_) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!("TLS handshake with {remote_addr} timed out");
+                        return;
+                    }
+                };
+            let info = TlsInfo::from_connection(stream.get_ref().1);
+            let service = hyper::service::service_fn(move |mut req: Request<Incoming>| {
+                req.extensions_mut()
+                    .insert(ConnectInfo::<SocketAddr>(remote_addr));
+                req.extensions_mut().insert(info.clone());
+                router.clone().oneshot(req.map(Body::new))
+            });
+            let conn = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            if let Err(_e) = watcher.watch(conn.into_owned()).await {
+                #[cfg(feature = "tracing")]
+                tracing::warn!("error serving connection from {remote_addr}: {_e}");
+            }
+        });
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::sync::Arc;
+
+use opentelemetry::trace::TraceContextExt;
+use pyo3::{
+    prelude::*,
+    types::{PyBytes, PyDict, PyList},
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Where the W3C trace context is placed in the ASGI scope.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceContextPropagation {
+    /// As `traceparent` and `tracestate` headers, replacing any sent by the client. This works
+    /// with the standard opentelemetry ASGI instrumentation without any changes on the python side.
+    Headers,
+    /// As a `{"traceparent": ..., "tracestate": ...}` dict in `scope["extensions"]["opentelemetry"]`.
+    Extension,
+}
+
+/// Propagates the opentelemetry trace context of the request into the ASGI application, so
+/// spans created in python are linked to the spans created in rust.
+///
+/// The parent is the `asgi_request` span created for each forwarded request, which requires a
+/// `tracing_opentelemetry` layer to be registered. If that span has no valid opentelemetry
+/// context, the current opentelemetry context is used instead.
+#[derive(Clone)]
+pub struct TraceContext {
+    propagation: TraceContextPropagation,
+    context_var: Option<Arc<PyObject>>,
+}
+
+impl TraceContext {
+    pub fn new(propagation: TraceContextPropagation) -> TraceContext {
+        TraceContext {
+            propagation,
+            context_var: None,
+        }
+    }
+
+    /// Also set the given python `contextvars.ContextVar` to the trace context carrier dict for
+    /// each request, so it can be used with `opentelemetry.propagate.extract(var.get())`.
+    pub fn with_context_var(mut self, context_var: PyObject) -> TraceContext {
+        self.context_var = Some(Arc::new(context_var));
+        self
+    }
+
+    /// Add the trace context to the scope, returning the task locals the request should be
+    /// run with if a context variable needs to be set
+    pub(crate) fn inject(
+        &self,
+        py: Python<'_>,
+        span: &tracing::Span,
+        headers: &Bound<'_, PyList>,
+        extensions: &Bound<'_, PyDict>,
+        locals: &pyo3_async_runtimes::TaskLocals,
+    ) -> PyResult<Option<pyo3_async_runtimes::TaskLocals>> {
+        let Some((traceparent, tracestate)) = carrier(span) else {
+            return Ok(None);
+        };
+
+        match self.propagation {
+            TraceContextPropagation::Headers => {
+                let existing = headers
+                    .iter()
+                    .map(|header| {
+                        let name: Vec<u8> = header.get_item(0)?.extract()?;
+                        Ok(name == b"traceparent" || name == b"tracestate")
+                    })
+                    .collect::<PyResult<Vec<_>>>()?;
+                for (index, _) in existing.iter().enumerate().rev().filter(|(_, e)| **e) {
+                    headers.del_item(index)?;
+                }
+                headers.append(header(py, "traceparent", &traceparent)?)?;
+                if let Some(tracestate) = &tracestate {
+                    headers.append(header(py, "tracestate", tracestate)?)?;
+                }
+            }
+            TraceContextPropagation::Extension => {
+                extensions.set_item(
+                    "opentelemetry",
+                    carrier_dict(py, &traceparent, &tracestate)?,
+                )?;
+            }
+        }
+
+        match &self.context_var {
+            Some(context_var) => {
+                let context = locals.context(py).call_method0("copy")?;
+                let carrier = carrier_dict(py, &traceparent, &tracestate)?;
+                context.call_method1("run", (context_var.getattr(py, "set")?, carrier))?;
+                Ok(Some(
+                    pyo3_async_runtimes::TaskLocals::new(locals.event_loop(py))
+                        .with_context(context),
+                ))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Format the span's context as W3C `traceparent` and `tracestate` values
+/// https://www.w3.org/TR/trace-context/
+fn carrier(span: &tracing::Span) -> Option<(String, Option<String>)> {
+    let context = span.context();
+    let span_context = if context.span().span_context().is_valid() {
+        context.span().span_context().clone()
+    } else {
+        opentelemetry::Context::current()
+            .span()
+            .span_context()
+            .clone()
+    };
+    if !span_context.is_valid() {
+        return None;
+    }
+    let traceparent = format!(
+        "00-{}-{}-{:02x}",
+        span_context.trace_id(),
+        span_context.span_id(),
+        span_context.trace_flags().to_u8()
+    );
+    let tracestate = span_context.trace_state().header();
+    Some((traceparent, (!tracestate.is_empty()).then_some(tracestate)))
+}
+
+fn carrier_dict<'py>(
+    py: Python<'py>,
+    traceparent: &str,
+    tracestate: &Option<String>,
+) -> PyResult<Bound<'py, PyDict>> {
+    let carrier = PyDict::new(py);
+    carrier.set_item("traceparent", traceparent)?;
+    if let Some(tracestate) = tracestate {
+        carrier.set_item("tracestate", tracestate)?;
+    }
+    Ok(carrier)
+}
+
+fn header<'py>(py: Python<'py>, name: &str, value: &str) -> PyResult<Bound<'py, PyList>> {
+    PyList::new(
+        py,
+        [
+            PyBytes::new(py, name.as_bytes()),
+            PyBytes::new(py, value.as_bytes()),
+        ],
+    )
+}
@@ -0,0 +1,241 @@
+# The python side of parviocula's out of process workers, see `AsgiWorkers`.
+#
+# Started as `python -c <this file> <module:app>`, with one end of a socket pair as stdin. The
+# worker imports the app, runs its lifespan startup, sends ready over the socket and serves
+# requests until it's told to shut down (or the socket closes), then runs the lifespan shutdown.
+#
+# Every frame is a header of (payload length: u32, stream id: u32, kind: u8), big endian,
+# followed by the payload. The start of requests and responses is json, body frames are a
+# `more_body` byte followed by at most 64KB of the body.
+#
+# Each side sends at most `WINDOW` body frames of a request that the other side didn't
+# acknowledge yet, with an empty ACK frame once the application (or the client) took the body
+# frame, so a slow reader only holds up its own request.
+
+import asyncio
+import importlib
+import json
+import os
+import socket
+import struct
+import sys
+import traceback
+
+HEADER = struct.Struct(">IIB")
+(
+    REQUEST_START,
+    REQUEST_BODY,
+    RESPONSE_START,
+    RESPONSE_BODY,
+    DISCONNECT,
+    ERROR,
+    READY,
+    SHUTDOWN,
+    ACK,
+) = range(9)
+
+MAX_CHUNK_SIZE = 64 * 1024
+WINDOW = 16
+
+
+def load(target):
+    module, _, attr = target.partition(":")
+    app = importlib.import_module(module)
+    for name in (attr or "app").split("."):
+        app = getattr(app, name)
+    return app
+
+
+class Connection:
+    def __init__(self, reader, writer):
+        self.reader = reader
+        self.writer = writer
+        self.lock = asyncio.Lock()
+
+    async def read(self):
+        length, stream, kind = HEADER.unpack(await self.reader.readexactly(HEADER.size))
+        return stream, kind, await self.reader.readexactly(length)
+
+    async def write(self, stream, kind, payload=b""):
+        self.writer.write(HEADER.pack(len(payload), stream, kind) + payload)
+        async with self.lock:
+            await self.writer.drain()
+
+
+class Stream:
+    """A request in flight"""
+
+    def __init__(self):
+        self.messages = asyncio.Queue()
+        self.window = asyncio.Semaphore(WINDOW)
+        self.disconnected = False
+
+    def disconnect(self):
+        self.disconnected = True
+        self.messages.put_nowait({"type": "http.disconnect"})
+        # the client is gone, wake up a response waiting for its acknowledgement
+        self.window.release()
+
+
+async def start_lifespan(app, state):
+    loop = asyncio.get_running_loop()
+    messages = asyncio.Queue()
+    startup = loop.create_future()
+    shutdown = loop.create_future()
+
+    async def send(message):
+        kind = message["type"]
+        future = startup if kind.startswith("lifespan.startup") else shutdown
+        if not future.done():
+            if kind.endswith(".failed"):
+                future.set_exception(RuntimeError(message.get("message") or kind))
+            else:
+                future.set_result(None)
+
+    scope = {
+        "type": "lifespan",
+        "asgi": {"version": "3.0", "spec_version": "2.0"},
+        "state": state,
+    }
+    task = asyncio.ensure_future(app(scope, messages.get, send))
+    await messages.put({"type": "lifespan.startup"})
+    await asyncio.wait([startup, task], return_when=asyncio.FIRST_COMPLETED)
+    if not startup.done():
+        # like uvicorn's `--lifespan auto`, apps without lifespan support still run
+        if not task.cancelled():
+            task.exception()
+        return None
+    startup.result()
+
+    async def stop():
+        await messages.put({"type": "lifespan.shutdown"})
+        await asyncio.wait([shutdown, task], return_when=asyncio.FIRST_COMPLETED)
+        if shutdown.done():
+            shutdown.result()
+
+    return stop
+
+
+def make_scope(payload, state):
+    scope = json.loads(payload)
+    scope["headers"] = [
+        (name.encode("latin-1"), value.encode("latin-1"))
+        for name, value in scope["headers"]
+    ]
+    scope["raw_path"] = scope["raw_path"].encode("latin-1")
+    scope["query_string"] = scope["query_string"].encode("latin-1")
+    for key in ("client", "server"):
+        if scope.get(key) is not None:
+            scope[key] = tuple(scope[key])
+    scope["state"] = dict(state)
+    return scope
+
+
+async def run(connection, app, stream_id, scope, stream, streams):
+    finished = False
+
+    async def receive():
+        message = await stream.messages.get()
+        if message["type"] == "http.request":
+            await connection.write(stream_id, ACK)
+        return message
+
+    async def send_body(more_body, body):
+        if stream.disconnected:
+            return
+        await stream.window.acquire()
+        if not stream.disconnected:
+            payload = bytes([more_body]) + body
+            await connection.write(stream_id, RESPONSE_BODY, payload)
+
+    async def send(message):
+        nonlocal finished
+        kind = message["type"]
+        if kind == "http.response.start":
+            headers = [
+                [bytes(name).decode("latin-1"), bytes(value).decode("latin-1")]
+                for name, value in message.get("headers", [])
+            ]
+            start = {"status": message["status"], "headers": headers}
+            await connection.write(stream_id, RESPONSE_START, json.dumps(start).encode())
+        elif kind == "http.response.body":
+            more_body = bool(message.get("more_body", False))
+            body = memoryview(bytes(message.get("body", b"")))
+            chunks = [
+                body[offset : offset + MAX_CHUNK_SIZE]
+                for offset in range(0, len(body), MAX_CHUNK_SIZE)
+            ] or [body]
+            for chunk in chunks[:-1]:
+                await send_body(True, chunk)
+            await send_body(more_body, chunks[-1])
+            if not more_body:
+                finished = True
+                stream.messages.put_nowait({"type": "http.disconnect"})
+
+    try:
+        await app(scope, receive, send)
+        if not finished:
+            raise RuntimeError("application returned without completing the response")
+    except Exception as e:
+        traceback.print_exc()
+        await connection.write(stream_id, ERROR, repr(e).encode())
+    finally:
+        streams.pop(stream_id, None)
+
+
+def take_socket():
+    """The socket pair passed as stdin, with stdin replaced by /dev/null"""
+    sock = socket.socket(fileno=os.dup(0))
+    devnull = os.open(os.devnull, os.O_RDONLY)
+    os.dup2(devnull, 0)
+    os.close(devnull)
+    return sock
+
+
+async def main(sock, target):
+    app = load(target)
+    state = {}
+    stop = await start_lifespan(app, state)
+    connection = Connection(*await asyncio.open_unix_connection(sock=sock))
+    await connection.write(0, READY)
+
+    streams = {}
+    tasks = set()
+    try:
+        while True:
+            stream, kind, payload = await connection.read()
+            if kind == SHUTDOWN:
+                break
+            if kind == REQUEST_START:
+                streams[stream] = Stream()
+                scope = make_scope(payload, state)
+                task = asyncio.ensure_future(
+                    run(connection, app, stream, scope, streams[stream], streams)
+                )
+                tasks.add(task)
+                task.add_done_callback(tasks.discard)
+            elif kind == REQUEST_BODY and stream in streams:
+                # never more than a window of these, as the rust side waits for the ACKs
+                streams[stream].messages.put_nowait(
+                    {
+                        "type": "http.request",
+                        "body": payload[1:],
+                        "more_body": bool(payload[0]),
+                    }
+                )
+            elif kind == ACK and stream in streams:
+                streams[stream].window.release()
+            elif kind == DISCONNECT and stream in streams:
+                streams[stream].disconnect()
+    except asyncio.IncompleteReadError:
+        pass
+
+    # let the requests in flight finish before shutting down
+    if tasks:
+        await asyncio.wait(tasks)
+    if stop is not None:
+        await stop()
+
+
+if __name__ == "__main__":
+    asyncio.run(main(take_socket(), sys.argv[1]))
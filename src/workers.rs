@@ -0,0 +1,741 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    future::Future,
+    io,
+    os::{fd::OwnedFd, unix::net::UnixStream as StdUnixStream},
+    path::PathBuf,
+    pin::Pin,
+    process::{ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError, RwLock,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    handler::Handler,
+    http::{request::Parts, HeaderName, HeaderValue, Request, StatusCode, Version},
+    response::{IntoResponse, Response},
+};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+        UnixStream,
+    },
+    process::Command,
+    runtime::Handle,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot, watch, OwnedSemaphorePermit, Semaphore,
+    },
+    task::JoinHandle,
+};
+
+use crate::{
+    asgi::Backend,
+    forwarded::{ForwardedInfo, ProxyHeaders},
+    request::mount_path,
+    wsgi::latin1,
+};
+
+const WORKER_SCRIPT: &str = include_str!("worker.py");
+
+// the frame kinds, see worker.py
+const REQUEST_START: u8 = 0;
+const REQUEST_BODY: u8 = 1;
+const RESPONSE_START: u8 = 2;
+const RESPONSE_BODY: u8 = 3;
+const DISCONNECT: u8 = 4;
+const ERROR: u8 = 5;
+const READY: u8 = 6;
+const SHUTDOWN: u8 = 7;
+const ACK: u8 = 8;
+
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024; // 64MB
+/// Bodies are split into frames of at most this size
+const MAX_CHUNK_SIZE: usize = 64 * 1024; // 64KB
+/// The number of body frames of a request sent before the other side acknowledges them, in
+/// both directions, so a slow reader only holds up its own request. See worker.py.
+const WINDOW: usize = 16;
+/// The number of frames waiting to be written to a worker
+const FRAME_QUEUE: usize = 64;
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+struct Frame {
+    stream: u32,
+    kind: u8,
+    payload: Bytes,
+}
+
+impl Frame {
+    fn new(stream: u32, kind: u8, payload: Bytes) -> Frame {
+        Frame {
+            stream,
+            kind,
+            payload,
+        }
+    }
+
+    fn body(stream: u32, kind: u8, more_body: bool, data: &[u8]) -> Frame {
+        let mut payload = Vec::with_capacity(data.len() + 1);
+        payload.push(more_body as u8);
+        payload.extend_from_slice(data);
+        Frame::new(stream, kind, payload.into())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(self.payload.len() + 9);
+        frame.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&self.stream.to_be_bytes());
+        frame.push(self.kind);
+        frame.extend_from_slice(&self.payload);
+        frame
+    }
+}
+
+async fn read_frame(reader: &mut OwnedReadHalf) -> io::Result<Frame> {
+    let mut header = [0; 9];
+    reader.read_exact(&mut header).await?;
+    let [l0, l1, l2, l3, s0, s1, s2, s3, kind] = header;
+    let length = u32::from_be_bytes([l0, l1, l2, l3]) as usize;
+    let stream = u32::from_be_bytes([s0, s1, s2, s3]);
+    if length > MAX_FRAME_SIZE {
+        // skip it, failing only the request it belongs to
+        tokio::io::copy(
+            &mut (&mut *reader).take(length as u64),
+            &mut tokio::io::sink(),
+        )
+        .await?;
+        let reason = format!("frame of {length} bytes is too large");
+        return Ok(Frame::new(stream, ERROR, reason.into()));
+    }
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload).await?;
+    Ok(Frame::new(stream, kind, payload.into()))
+}
+
+/// The receiving side of a request in flight
+struct StreamHandle {
+    responses: mpsc::Sender<Frame>,
+    /// Credits for sending request body frames, added back as the worker acknowledges them
+    request_window: Arc<Semaphore>,
+}
+
+type Streams = Arc<Mutex<HashMap<u32, StreamHandle>>>;
+
+/// Dispatch the frames sent by the worker to the requests they belong to. This never waits on
+/// a request, the worker sends at most a window of body frames that weren't acknowledged yet.
+async fn read_frames(mut reader: OwnedReadHalf, streams: Streams) -> io::Result<()> {
+    loop {
+        let frame = read_frame(&mut reader).await?;
+        let mut streams = streams.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(stream) = streams.get(&frame.stream) else {
+            continue;
+        };
+        if frame.kind == ACK {
+            stream.request_window.add_permits(1);
+            continue;
+        }
+        let id = frame.stream;
+        if stream.responses.try_send(frame).is_err() {
+            // the worker ignored the window, or the request is gone
+            streams.remove(&id);
+        }
+    }
+}
+
+async fn write_frames(
+    mut writer: OwnedWriteHalf,
+    mut frames: mpsc::Receiver<Frame>,
+) -> io::Result<()> {
+    while let Some(frame) = frames.recv().await {
+        writer.write_all(&frame.encode()).await?;
+    }
+    Ok(())
+}
+
+/// The connection to a running worker, multiplexing requests by stream id
+#[derive(Clone)]
+struct Connection {
+    frames: mpsc::Sender<Frame>,
+    streams: Streams,
+    next_stream: Arc<AtomicU32>,
+}
+
+struct Worker {
+    connection: RwLock<Option<Connection>>,
+    permits: Arc<Semaphore>,
+}
+
+impl Worker {
+    fn set_connection(&self, connection: Option<Connection>) {
+        *self
+            .connection
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = connection;
+    }
+}
+
+/// A request in flight on a worker. Dropping it tells the worker the client disconnected.
+struct Stream {
+    id: u32,
+    connection: Connection,
+    responses: mpsc::Receiver<Frame>,
+    request_window: Arc<Semaphore>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Stream {
+    fn open(connection: Connection, permit: OwnedSemaphorePermit) -> Stream {
+        let id = connection.next_stream.fetch_add(1, Ordering::Relaxed);
+        // room for the response start and an error besides the body frames
+        let (responses_tx, responses) = mpsc::channel(WINDOW + 2);
+        let request_window = Arc::new(Semaphore::new(WINDOW));
+        connection
+            .streams
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                id,
+                StreamHandle {
+                    responses: responses_tx,
+                    request_window: request_window.clone(),
+                },
+            );
+        Stream {
+            id,
+            connection,
+            responses,
+            request_window,
+            _permit: permit,
+        }
+    }
+
+    /// Let the worker send the next response body frame
+    async fn ack(&self) {
+        let ack = Frame::new(self.id, ACK, Bytes::new());
+        let _ = self.connection.frames.send(ack).await;
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.connection
+            .streams
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.id);
+        // stops sending the request body
+        self.request_window.close();
+        let disconnect = Frame::new(self.id, DISCONNECT, Bytes::new());
+        if let Err(TrySendError::Full(disconnect)) = self.connection.frames.try_send(disconnect) {
+            if let Ok(handle) = Handle::try_current() {
+                let frames = self.connection.frames.clone();
+                handle.spawn(async move { frames.send(disconnect).await });
+            }
+        }
+    }
+}
+
+/// Configuration of a pool of python worker processes running an ASGI application, as an
+/// alternative to running it in process with the [`AsgiHandler`](crate::AsgiHandler).
+///
+/// Each worker imports the application, runs its lifespan and serves requests on its own event
+/// loop, so the python side can use more than one core, and a crash in a C extension only takes
+/// down one worker. Requests are sent to the least busy worker, over a socket pair the worker
+/// inherits as its stdin, so no other process can connect to it, and crashed workers are
+/// restarted. Request and response bodies are streamed in frames of at most 64KB, and each
+/// request only has a few frames in flight in either direction, so slow clients or slow python
+/// code don't buffer whole bodies in memory.
+///
+/// Unlike the in process handler, the scope can't include python objects, so extension
+/// converters and rust lifespan state aren't available.
+///
+/// ```rust,ignore
+/// let workers = AsgiWorkers::new("myproject.main:app")
+///     .with_workers(4)
+///     .with_max_concurrency(50)
+///     .start()
+///     .await?;
+/// let app = Router::new()
+///     .route("/users", get(get_users))
+///     .fallback(workers.clone());
+/// axum::serve(listener, app).await?;
+/// workers.shutdown().await;
+/// ```
+#[derive(Clone)]
+pub struct AsgiWorkers {
+    app: String,
+    python: PathBuf,
+    workers: usize,
+    max_concurrency: usize,
+    startup_timeout: Duration,
+    shutdown_timeout: Duration,
+    proxy_headers: Option<ProxyHeaders>,
+}
+
+impl AsgiWorkers {
+    /// `app` is the application as `module:attribute`, like uvicorn's
+    pub fn new(app: impl Into<String>) -> AsgiWorkers {
+        AsgiWorkers {
+            app: app.into(),
+            python: PathBuf::from("python3"),
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            max_concurrency: 100,
+            startup_timeout: Duration::from_secs(60),
+            shutdown_timeout: Duration::from_secs(30),
+            proxy_headers: None,
+        }
+    }
+
+    /// The python interpreter to run the workers with. Defaults to `python3`.
+    pub fn with_python(mut self, python: impl Into<PathBuf>) -> Self {
+        self.python = python.into();
+        self
+    }
+
+    /// The number of worker processes. Defaults to the number of cores.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// The maximum number of requests handled by one worker at the same time. Defaults to 100.
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency;
+        self
+    }
+
+    /// How long a worker may take to import the app and run its lifespan startup. Defaults to
+    /// 60 seconds.
+    pub fn with_startup_timeout(mut self, startup_timeout: Duration) -> Self {
+        self.startup_timeout = startup_timeout;
+        self
+    }
+
+    /// How long a worker may take to finish its requests and lifespan shutdown before it's
+    /// killed. Defaults to 30 seconds.
+    pub fn with_shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// See [`AsgiHandler::with_proxy_headers`](crate::AsgiHandler::with_proxy_headers)
+    pub fn with_proxy_headers(mut self, proxy_headers: ProxyHeaders) -> Self {
+        self.proxy_headers = Some(proxy_headers);
+        self
+    }
+
+    /// Start the workers, returning once every worker has completed its lifespan startup
+    pub async fn start(self) -> io::Result<AsgiWorkerPool> {
+        let config = Arc::new(self);
+        let (shutdown, _) = watch::channel(false);
+        let mut workers = Vec::new();
+        let mut supervisors = Vec::new();
+        let mut started = Vec::new();
+        for index in 0..config.workers {
+            let worker = Arc::new(Worker {
+                connection: RwLock::new(None),
+                permits: Arc::new(Semaphore::new(config.max_concurrency)),
+            });
+            let (started_tx, started_rx) = oneshot::channel();
+            supervisors.push(tokio::spawn(supervise(
+                config.clone(),
+                index,
+                worker.clone(),
+                shutdown.subscribe(),
+                started_tx,
+            )));
+            workers.push(worker);
+            started.push(started_rx);
+        }
+        let pool = AsgiWorkerPool {
+            inner: Arc::new(PoolInner {
+                workers,
+                next: AtomicUsize::new(0),
+                proxy_headers: config.proxy_headers.clone(),
+                shutdown,
+                supervisors: Mutex::new(supervisors),
+            }),
+        };
+        for (index, started) in started.into_iter().enumerate() {
+            let started = started
+                .await
+                .unwrap_or_else(|_| Err(io::Error::other("startup was interrupted")));
+            if let Err(e) = started {
+                pool.shutdown().await;
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("worker {index} failed to start: {e}"),
+                ));
+            }
+        }
+        Ok(pool)
+    }
+}
+
+/// Keep a worker running, restarting it when it crashes
+async fn supervise(
+    config: Arc<AsgiWorkers>,
+    index: usize,
+    worker: Arc<Worker>,
+    mut shutdown: watch::Receiver<bool>,
+    started: oneshot::Sender<io::Result<()>>,
+) {
+    let mut started = Some(started);
+    while !*shutdown.borrow() {
+        let result = run_worker(&config, index, &worker, &mut shutdown, &mut started).await;
+        match result {
+            Ok(()) => break,
+            Err(e) => {
+                // a worker that can't start at all fails `AsgiWorkers::start`
+                if let Some(started) = started.take() {
+                    let _ = started.send(Err(e));
+                    break;
+                }
+                #[cfg(feature = "tracing")]
+                tracing::warn!("ASGI worker {index} crashed, restarting: {e}");
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(RESTART_DELAY) => {}
+            _ = shutdown.changed() => break,
+        }
+    }
+}
+
+fn exited(status: io::Result<ExitStatus>) -> io::Error {
+    match status {
+        Ok(status) => io::Error::other(format!("worker exited with {status}")),
+        Err(e) => e,
+    }
+}
+
+/// Run one worker process until it crashes (an error) or the pool shuts down
+async fn run_worker(
+    config: &AsgiWorkers,
+    _index: usize,
+    worker: &Worker,
+    shutdown: &mut watch::Receiver<bool>,
+    started: &mut Option<oneshot::Sender<io::Result<()>>>,
+) -> io::Result<()> {
+    let (socket, worker_socket) = StdUnixStream::pair()?;
+    socket.set_nonblocking(true)?;
+    let socket = UnixStream::from_std(socket)?;
+    let mut child = Command::new(&config.python)
+        .arg("-c")
+        .arg(WORKER_SCRIPT)
+        .arg(&config.app)
+        .stdin(Stdio::from(OwnedFd::from(worker_socket)))
+        .kill_on_drop(true)
+        .spawn()?;
+
+    // the worker sends ready once its lifespan startup completed
+    let accept = async {
+        let (mut reader, writer) = socket.into_split();
+        let ready = read_frame(&mut reader).await?;
+        if ready.kind != READY {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "worker didn't send ready",
+            ));
+        }
+        Ok((reader, writer))
+    };
+    let accepted = tokio::select! {
+        accepted = tokio::time::timeout(config.startup_timeout, accept) => accepted
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "worker startup timed out"))),
+        status = child.wait() => Err(exited(status)),
+        _ = shutdown.changed() => return Ok(()),
+    };
+    let (reader, writer) = accepted?;
+
+    let (frames, frames_rx) = mpsc::channel(FRAME_QUEUE);
+    let connection = Connection {
+        frames,
+        streams: Streams::default(),
+        next_stream: Arc::new(AtomicU32::new(1)),
+    };
+    let streams = connection.streams.clone();
+    let mut io: JoinHandle<io::Result<()>> = tokio::spawn(async move {
+        tokio::select! {
+            result = read_frames(reader, streams) => result,
+            result = write_frames(writer, frames_rx) => result,
+        }
+    });
+    worker.set_connection(Some(connection.clone()));
+    if let Some(started) = started.take() {
+        let _ = started.send(Ok(()));
+    }
+    #[cfg(feature = "tracing")]
+    tracing::info!("ASGI worker {_index} started with pid {:?}", child.id());
+
+    let result = tokio::select! {
+        status = child.wait() => Err(exited(status)),
+        result = &mut io => Err(match result {
+            Ok(Ok(())) => io::Error::other("worker connection closed"),
+            Ok(Err(e)) => e,
+            Err(e) => io::Error::other(e),
+        }),
+        _ = shutdown.changed() => Ok(()),
+    };
+    worker.set_connection(None);
+    if result.is_ok() {
+        // stop sending requests, and let the worker finish the ones in flight
+        let stop = async {
+            let _ = connection
+                .frames
+                .send(Frame::new(0, SHUTDOWN, Bytes::new()))
+                .await;
+            child.wait().await
+        };
+        if tokio::time::timeout(config.shutdown_timeout, stop)
+            .await
+            .is_err()
+        {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("ASGI worker {_index} didn't shut down in time, killing it");
+            let _ = child.kill().await;
+        }
+    }
+    io.abort();
+    // fails the requests still in flight
+    connection
+        .streams
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clear();
+    result
+}
+
+struct PoolInner {
+    workers: Vec<Arc<Worker>>,
+    next: AtomicUsize,
+    proxy_headers: Option<ProxyHeaders>,
+    shutdown: watch::Sender<bool>,
+    supervisors: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// A running pool of python workers, used as an axum handler in place of an
+/// [`AsgiHandler`](crate::AsgiHandler). See [`AsgiWorkers`].
+///
+/// Responses are marked as served by python with [`Backend::Asgi`]. Requests get a 503 while no
+/// worker is running, and a 502 when their worker crashes.
+#[derive(Clone)]
+pub struct AsgiWorkerPool {
+    inner: Arc<PoolInner>,
+}
+
+impl AsgiWorkerPool {
+    /// Stop the workers, waiting for them to finish their requests and lifespan shutdown
+    pub async fn shutdown(&self) {
+        self.inner.shutdown.send_replace(true);
+        let supervisors = std::mem::take(
+            &mut *self
+                .inner
+                .supervisors
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        for supervisor in supervisors {
+            let _ = supervisor.await;
+        }
+    }
+
+    /// The running worker with the most free capacity, preferring workers in round robin order
+    fn pick(&self) -> Option<(Arc<Worker>, Connection)> {
+        let workers = &self.inner.workers;
+        let start = self.inner.next.fetch_add(1, Ordering::Relaxed);
+        (0..workers.len())
+            .map(|offset| &workers[(start + offset) % workers.len()])
+            .filter_map(|worker| {
+                let connection = worker
+                    .connection
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .clone()?;
+                Some((worker, connection))
+            })
+            .min_by_key(|(worker, _)| Reverse(worker.permits.available_permits()))
+            .map(|(worker, connection)| (worker.clone(), connection))
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Result<Response, StatusCode> {
+        let (worker, connection) = self.pick().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+        let permit = worker
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        let (parts, body) = req.into_parts();
+        let proxy_headers = self
+            .inner
+            .proxy_headers
+            .as_ref()
+            .or_else(|| parts.extensions.get::<ProxyHeaders>());
+        let forwarded = ForwardedInfo::from_parts(&parts, proxy_headers);
+        let scope = scope(&parts, forwarded).ok_or(StatusCode::BAD_REQUEST)?;
+
+        let mut stream = Stream::open(connection, permit);
+        let frames = stream.connection.frames.clone();
+        frames
+            .send(Frame::new(
+                stream.id,
+                REQUEST_START,
+                scope.to_string().into(),
+            ))
+            .await
+            .map_err(|_| StatusCode::BAD_GATEWAY)?;
+        tokio::spawn(send_body(
+            frames,
+            stream.id,
+            stream.request_window.clone(),
+            body,
+        ));
+
+        let start = loop {
+            match stream.responses.recv().await {
+                Some(frame) if frame.kind == RESPONSE_START => break frame,
+                Some(frame) if frame.kind == ERROR => {
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+                Some(_) => continue,
+                None => return Err(StatusCode::BAD_GATEWAY),
+            }
+        };
+        let start: Value =
+            serde_json::from_slice(&start.payload).map_err(|_| StatusCode::BAD_GATEWAY)?;
+        let mut response = Response::builder().status(
+            start["status"]
+                .as_u64()
+                .and_then(|status| u16::try_from(status).ok())
+                .ok_or(StatusCode::BAD_GATEWAY)?,
+        );
+        for header in start["headers"].as_array().into_iter().flatten() {
+            let (Some(name), Some(value)) = (header[0].as_str(), header[1].as_str()) else {
+                return Err(StatusCode::BAD_GATEWAY);
+            };
+            let encode = |s: &str| s.chars().map(|c| c as u8).collect::<Vec<_>>();
+            response = response.header(
+                HeaderName::from_bytes(&encode(name)).map_err(|_| StatusCode::BAD_GATEWAY)?,
+                HeaderValue::from_bytes(&encode(value)).map_err(|_| StatusCode::BAD_GATEWAY)?,
+            );
+        }
+        let body = futures::stream::unfold(Some(stream), |stream| async move {
+            let mut stream = stream?;
+            match stream.responses.recv().await {
+                Some(frame) if frame.kind == RESPONSE_BODY && !frame.payload.is_empty() => {
+                    let more_body = frame.payload[0] == 1;
+                    let data = frame.payload.slice(1..);
+                    if more_body {
+                        stream.ack().await;
+                    }
+                    Some((Ok(data), more_body.then_some(stream)))
+                }
+                Some(frame) if frame.kind == ERROR => Some((
+                    Err(io::Error::other(
+                        String::from_utf8_lossy(&frame.payload).into_owned(),
+                    )),
+                    None,
+                )),
+                _ => Some((Err(io::Error::other("ASGI worker crashed")), None)),
+            }
+        });
+        response
+            .body(Body::from_stream(body))
+            .map_err(|_| StatusCode::BAD_GATEWAY)
+    }
+}
+
+/// Stream the request body to the worker, waiting for it to acknowledge the frames sent so far
+/// once a window of frames is in flight. Stops when the request is dropped.
+async fn send_body(
+    frames: mpsc::Sender<Frame>,
+    stream: u32,
+    window: Arc<Semaphore>,
+    mut body: Body,
+) {
+    let send = |more_body: bool, data: &[u8]| {
+        let frame = Frame::body(stream, REQUEST_BODY, more_body, data);
+        let (frames, window) = (&frames, &window);
+        async move {
+            let Ok(permit) = window.acquire().await else {
+                return false;
+            };
+            permit.forget();
+            frames.send(frame).await.is_ok()
+        }
+    };
+    while let Some(frame) = body.frame().await {
+        let Ok(frame) = frame else {
+            return;
+        };
+        let Ok(data) = frame.into_data() else {
+            continue;
+        };
+        for chunk in data.chunks(MAX_CHUNK_SIZE) {
+            if !send(true, chunk).await {
+                return;
+            }
+        }
+    }
+    send(false, &[]).await;
+}
+
+/// The http scope, with bytes as latin-1 strings
+fn scope(parts: &Parts, forwarded: ForwardedInfo) -> Option<Value> {
+    let (root_path, raw_path) = mount_path(parts);
+    let decode = |path: &str| {
+        percent_encoding::percent_decode(path.as_bytes())
+            .decode_utf8()
+            .map(|path| path.into_owned())
+            .ok()
+    };
+    let (root_path, path) = (decode(root_path)?, decode(raw_path)?);
+    let headers = parts
+        .headers
+        .iter()
+        .map(|(name, value)| json!([name.as_str(), latin1(value.as_bytes())]))
+        .collect::<Vec<_>>();
+    Some(json!({
+        "type": "http",
+        "asgi": {"version": "3.0", "spec_version": "2.3"},
+        "http_version": match parts.version {
+            Version::HTTP_10 => "1.0",
+            Version::HTTP_11 => "1.1",
+            Version::HTTP_2 => "2",
+            _ => return None,
+        },
+        "method": parts.method.as_str(),
+        "scheme": forwarded.scheme,
+        "path": path,
+        "raw_path": raw_path,
+        "query_string": parts.uri.query().unwrap_or(""),
+        "root_path": root_path,
+        "headers": headers,
+        "client": forwarded.client,
+        "server": forwarded.server,
+    }))
+}
+
+impl<S> Handler<AsgiWorkerPool, S> for AsgiWorkerPool {
+    type Future = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+    fn call(self, req: Request<Body>, _state: S) -> Self::Future {
+        Box::pin(async move {
+            let mut response = self
+                .handle(req)
+                .await
+                .unwrap_or_else(IntoResponse::into_response);
+            response.extensions_mut().insert(Backend::Asgi);
+            response
+        })
+    }
+}
@@ -0,0 +1,404 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    handler::Handler,
+    http::{
+        header, request::Parts, HeaderMap, HeaderName, HeaderValue, Request, StatusCode, Version,
+    },
+    response::{IntoResponse, Response},
+};
+use pyo3::{
+    exceptions::PyRuntimeError,
+    prelude::*,
+    types::{PyBytes, PyDict},
+};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+
+use crate::{
+    asgi::Backend,
+    forwarded::{ForwardedInfo, ProxyHeaders},
+    request::{body_error_status, mount_path, DEFAULT_MAX_BODY_SIZE},
+};
+
+const DEFAULT_MAX_THREADS: usize = 10;
+
+/// An axum handler for WSGI applications (e.g. Flask or Django), the WSGI counterpart of the
+/// [`AsgiHandler`](crate::AsgiHandler).
+///
+/// WSGI applications are synchronous, so each request runs on a blocking thread, with at most
+/// [`WsgiHandler::with_max_threads`] requests running at the same time. The request body is
+/// buffered before calling the application, while the response is streamed back as the
+/// application's iterable produces it.
+///
+/// Responses are marked as served by python with [`Backend::Asgi`].
+///
+/// ```rust,ignore
+/// let wsgi = WsgiHandler::new(Arc::new(flask_app)).with_max_threads(32);
+/// Router::new().route("/users", get(get_users)).fallback(wsgi)
+/// ```
+#[derive(Clone)]
+pub struct WsgiHandler {
+    app: Arc<PyObject>,
+    threads: Arc<Semaphore>,
+    proxy_headers: Option<ProxyHeaders>,
+    max_body_size: usize,
+}
+
+impl WsgiHandler {
+    pub fn new(app: Arc<PyObject>) -> WsgiHandler {
+        WsgiHandler {
+            app,
+            threads: Arc::new(Semaphore::new(DEFAULT_MAX_THREADS)),
+            proxy_headers: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Set the maximum number of requests handled at the same time. Defaults to 10.
+    pub fn with_max_threads(mut self, max_threads: usize) -> Self {
+        self.threads = Arc::new(Semaphore::new(max_threads));
+        self
+    }
+
+    /// Resolve `wsgi.url_scheme`, `REMOTE_ADDR`, `SERVER_NAME` and `SERVER_PORT` from the proxy
+    /// headers of requests coming from trusted proxies, see
+    /// [`AsgiHandler::with_proxy_headers`](crate::AsgiHandler::with_proxy_headers).
+    pub fn with_proxy_headers(mut self, proxy_headers: ProxyHeaders) -> Self {
+        self.proxy_headers = Some(proxy_headers);
+        self
+    }
+
+    /// Set the maximum size of the request body read into `wsgi.input`. Larger requests are
+    /// answered with `413 Payload Too Large`. Defaults to 4MB.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+}
+
+type Head = (StatusCode, HeaderMap);
+
+impl<S> Handler<WsgiHandler, S> for WsgiHandler {
+    type Future = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+    fn call(self, req: Request<Body>, _state: S) -> Self::Future {
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = match to_bytes(body, self.max_body_size).await {
+                Ok(body) => body,
+                Err(e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!("failed to buffer WSGI request body: {e}");
+                    return body_error_status(&e).into_response();
+                }
+            };
+            let proxy_headers = self
+                .proxy_headers
+                .as_ref()
+                .or_else(|| parts.extensions.get::<ProxyHeaders>());
+            let forwarded = ForwardedInfo::from_parts(&parts, proxy_headers);
+
+            let permit = self
+                .threads
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("the semaphore is never closed");
+            let (head_tx, head_rx) = oneshot::channel::<Head>();
+            let (body_tx, body_rx) = mpsc::channel::<Result<Bytes, PyErr>>(16);
+            let app = self.app.clone();
+            tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                if let Err(_e) = run(&app, &parts, body, forwarded, head_tx, &body_tx) {
+                    #[cfg(feature = "tracing")]
+                    tracing::error!("error handling WSGI request: {_e}");
+                    // if the response already started, end it with an error
+                    let _ = body_tx.blocking_send(Err(_e));
+                }
+            });
+
+            let mut response = match head_rx.await {
+                Ok((status, headers)) => {
+                    let body = futures::stream::unfold(body_rx, |mut rx| async move {
+                        rx.recv().await.map(|chunk| (chunk, rx))
+                    });
+                    let mut response = Response::new(Body::from_stream(body));
+                    *response.status_mut() = status;
+                    *response.headers_mut() = headers;
+                    response
+                }
+                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            };
+            response.extensions_mut().insert(Backend::Asgi);
+            response
+        })
+    }
+}
+
+type Started = Arc<Mutex<Option<(String, Vec<(String, String)>)>>>;
+
+/// The `start_response` callable passed to the application
+#[pyclass]
+struct StartResponse {
+    started: Started,
+    sent: Arc<AtomicBool>,
+    written: Arc<Mutex<Vec<Bytes>>>,
+}
+
+#[pymethods]
+impl StartResponse {
+    #[pyo3(signature = (status, headers, exc_info=None))]
+    fn __call__(
+        &self,
+        py: Python<'_>,
+        status: String,
+        headers: Vec<(String, String)>,
+        exc_info: Option<Bound<'_, PyAny>>,
+    ) -> PyResult<Py<Write>> {
+        let mut started = self.started.lock().unwrap_or_else(|e| e.into_inner());
+        match exc_info.filter(|exc_info| !exc_info.is_none()) {
+            // https://peps.python.org/pep-3333/#the-start-response-callable
+            Some(exc_info) if self.sent.load(Ordering::SeqCst) => {
+                return Err(PyErr::from_value(exc_info.get_item(1)?));
+            }
+            Some(_) => {}
+            None if started.is_some() => {
+                return Err(PyErr::new::<PyRuntimeError, _>(
+                    "start_response called again without exc_info",
+                ));
+            }
+            None => {}
+        }
+        *started = Some((status, headers));
+        Py::new(
+            py,
+            Write {
+                written: self.written.clone(),
+            },
+        )
+    }
+}
+
+/// The legacy `write` callable returned by `start_response`
+#[pyclass]
+struct Write {
+    written: Arc<Mutex<Vec<Bytes>>>,
+}
+
+#[pymethods]
+impl Write {
+    fn __call__(&self, data: Vec<u8>) {
+        self.written
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Bytes::from(data));
+    }
+}
+
+/// WSGI strings are bytes decoded as latin-1
+pub(crate) fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| *byte as char).collect()
+}
+
+/// Encode a WSGI string back into bytes, failing on characters outside of latin-1
+fn from_latin1(s: &str) -> Option<Vec<u8>> {
+    s.chars().map(|c| u8::try_from(c).ok()).collect()
+}
+
+fn environ<'py>(
+    py: Python<'py>,
+    parts: &Parts,
+    body: Bytes,
+    forwarded: ForwardedInfo,
+) -> PyResult<Bound<'py, PyDict>> {
+    let environ = PyDict::new(py);
+    environ.set_item("REQUEST_METHOD", parts.method.as_str())?;
+    // unlike ASGI's `path`, `PATH_INFO` doesn't include the mount prefix
+    let (script_name, _) = mount_path(parts);
+    let script_name = percent_encoding::percent_decode(script_name.as_bytes()).collect::<Vec<_>>();
+    environ.set_item("SCRIPT_NAME", latin1(&script_name))?;
+    let path = percent_encoding::percent_decode(parts.uri.path().as_bytes()).collect::<Vec<_>>();
+    environ.set_item("PATH_INFO", latin1(&path))?;
+    environ.set_item("QUERY_STRING", parts.uri.query().unwrap_or(""))?;
+    environ.set_item(
+        "SERVER_PROTOCOL",
+        match parts.version {
+            Version::HTTP_10 => "HTTP/1.0",
+            Version::HTTP_2 => "HTTP/2",
+            _ => "HTTP/1.1",
+        },
+    )?;
+    let default_port = if forwarded.scheme == "https" { 443 } else { 80 };
+    let (server_name, server_port) = match forwarded.server {
+        Some((host, port)) => (host, port.unwrap_or(default_port)),
+        None => ("localhost".to_string(), default_port),
+    };
+    environ.set_item("SERVER_NAME", server_name)?;
+    environ.set_item("SERVER_PORT", server_port.to_string())?;
+    if let Some((host, port)) = forwarded.client {
+        environ.set_item("REMOTE_ADDR", host)?;
+        environ.set_item("REMOTE_PORT", port.to_string())?;
+    }
+
+    for name in parts.headers.keys() {
+        // http/2 clients send every cookie as its own header
+        let separator = if name == header::COOKIE { "; " } else { ", " };
+        let value = parts
+            .headers
+            .get_all(name)
+            .iter()
+            .map(|value| latin1(value.as_bytes()))
+            .collect::<Vec<_>>()
+            .join(separator);
+        let key = match name.as_str() {
+            "content-type" => "CONTENT_TYPE".to_string(),
+            "content-length" => "CONTENT_LENGTH".to_string(),
+            name => format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_")),
+        };
+        environ.set_item(key, value)?;
+    }
+
+    environ.set_item("wsgi.version", (1, 0))?;
+    environ.set_item("wsgi.url_scheme", forwarded.scheme)?;
+    let input = py
+        .import("io")?
+        .getattr("BytesIO")?
+        .call1((PyBytes::new(py, &body),))?;
+    environ.set_item("wsgi.input", input)?;
+    environ.set_item("wsgi.errors", py.import("sys")?.getattr("stderr")?)?;
+    environ.set_item("wsgi.multithread", true)?;
+    environ.set_item("wsgi.multiprocess", false)?;
+    environ.set_item("wsgi.run_once", false)?;
+    Ok(environ)
+}
+
+fn head(started: &Started) -> PyResult<Head> {
+    let started = started.lock().unwrap_or_else(|e| e.into_inner());
+    let Some((status, headers)) = started.as_ref() else {
+        return Err(PyErr::new::<PyRuntimeError, _>(
+            "WSGI application didn't call start_response",
+        ));
+    };
+    let invalid = |what: &str| PyErr::new::<PyRuntimeError, _>(format!("invalid {what}"));
+    let status = status
+        .split_whitespace()
+        .next()
+        .and_then(|code| code.parse::<u16>().ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or_else(|| invalid("status"))?;
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        map.append(
+            HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid("header name"))?,
+            from_latin1(value)
+                .and_then(|value| HeaderValue::from_bytes(&value).ok())
+                .ok_or_else(|| invalid("header value"))?,
+        );
+    }
+    Ok((status, map))
+}
+
+/// Call the application and stream its response, on a blocking thread
+fn run(
+    app: &PyObject,
+    parts: &Parts,
+    body: Bytes,
+    forwarded: ForwardedInfo,
+    head_tx: oneshot::Sender<Head>,
+    body_tx: &mpsc::Sender<Result<Bytes, PyErr>>,
+) -> PyResult<()> {
+    let started = Started::default();
+    let sent = Arc::new(AtomicBool::new(false));
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let (result, iter) = Python::with_gil(|py| {
+        let environ = environ(py, parts, body, forwarded)?;
+        let start_response = StartResponse {
+            started: started.clone(),
+            sent: sent.clone(),
+            written: written.clone(),
+        };
+        let result = app.call1(py, (environ, Py::new(py, start_response)?))?;
+        let iter = result.bind(py).try_iter()?.into_any().unbind();
+        Ok::<_, PyErr>((result, iter))
+    })?;
+
+    let mut head_tx = Some(head_tx);
+    let mut send_head = || -> PyResult<()> {
+        if let Some(head_tx) = head_tx.take() {
+            let _ = head_tx.send(head(&started)?);
+            sent.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    };
+    let outcome = (|| {
+        loop {
+            // the GIL is released between chunks, so other threads can run
+            let chunk = Python::with_gil(|py| {
+                iter.bind(py)
+                    .call_method0("__next__")
+                    .map(|chunk| Some(chunk.extract::<Vec<u8>>()))
+                    .or_else(|e| {
+                        if e.is_instance_of::<pyo3::exceptions::PyStopIteration>(py) {
+                            Ok(None)
+                        } else {
+                            Err(e)
+                        }
+                    })
+            })?;
+            let Some(chunk) = chunk else {
+                break;
+            };
+            let chunk = chunk?;
+            let pending = std::mem::take(&mut *written.lock().unwrap_or_else(|e| e.into_inner()));
+            if chunk.is_empty() && pending.is_empty() {
+                continue;
+            }
+            send_head()?;
+            for data in pending.into_iter().chain([Bytes::from(chunk)]) {
+                if body_tx.blocking_send(Ok(data)).is_err() {
+                    // the client disconnected
+                    return Ok(());
+                }
+            }
+        }
+        send_head()?;
+        let pending = std::mem::take(&mut *written.lock().unwrap_or_else(|e| e.into_inner()));
+        for data in pending {
+            if body_tx.blocking_send(Ok(data)).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    })();
+
+    // https://peps.python.org/pep-3333/#specification-details
+    let closed = Python::with_gil(|py| {
+        let result = result.bind(py);
+        if result.hasattr("close")? {
+            result.call_method0("close")?;
+        }
+        Ok::<_, PyErr>(())
+    });
+    outcome.and(closed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_latin1() {
+        let bytes = b"caf\xe9 \x80";
+        assert_eq!(latin1(bytes), "caf\u{e9} \u{80}");
+        assert_eq!(from_latin1(&latin1(bytes)).unwrap(), bytes);
+        assert_eq!(from_latin1("\u{20ac}"), None);
+    }
+}